@@ -0,0 +1,111 @@
+use crate::gelf::error::GelfError;
+use crate::health;
+use crate::metrics;
+use hyper::header::CONTENT_TYPE;
+use hyper::http::Result as HttpResult;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::error;
+use std::convert::Infallible;
+use tokio::net::{lookup_host, ToSocketAddrs};
+
+/// Path, which exposes prometheus metrics
+pub const METRICS_PATH: &str = "/metrics";
+/// Path of the liveness probe, which fails when the actors pipeline is stalled
+pub const HEALTH_PATH: &str = "/healthz";
+/// Path of the readiness probe, which fails until listeners are bound and the sentry is reachable,
+/// and during the shutdown
+pub const READY_PATH: &str = "/readyz";
+
+/// Http server for the operators of gtsa
+pub async fn new_admin_server<T>(bind_addr: T)
+where
+    T: ToSocketAddrs,
+{
+    let addr = lookup_host(bind_addr)
+        .await
+        .unwrap()
+        .next()
+        .unwrap_or_else(|| panic!("admin address is not resolved"));
+
+    let make_service =
+        make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle_request)) });
+
+    if let Err(e) = Server::bind(&addr).serve(make_service).await {
+        error!("{}", GelfError::from_err("admin server error", e));
+    }
+}
+
+async fn handle_request(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::GET, METRICS_PATH) => {
+            let (content_type, buf) = metrics::encode();
+            Response::builder()
+                .header(CONTENT_TYPE, content_type)
+                .body(Body::from(buf))
+        }
+        (&Method::GET, HEALTH_PATH) => probe(health::liveness()),
+        (&Method::GET, READY_PATH) => probe(health::readiness()),
+        (_, METRICS_PATH) | (_, HEALTH_PATH) | (_, READY_PATH) => Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::empty()),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+
+    Ok(response.unwrap())
+}
+
+fn probe(result: Result<(), Vec<String>>) -> HttpResult<Response<Body>> {
+    match result {
+        Ok(()) => Response::builder().body(Body::from("ok\n")),
+        Err(problems) => Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(Body::from(problems.join("\n") + "\n")),
+    }
+}
+
+#[cfg(test)]
+mod server {
+    use super::*;
+
+    fn request(method: Method, path: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn test_metrics() {
+        metrics::PARSE_FAILURES.inc();
+
+        let response = handle_request(request(Method::GET, METRICS_PATH))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("gtsa_parse_failures_total"));
+
+        let response = handle_request(request(Method::POST, METRICS_PATH))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let response = handle_request(request(Method::GET, HEALTH_PATH))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = handle_request(request(Method::POST, READY_PATH))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let response = handle_request(request(Method::GET, "/")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
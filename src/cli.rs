@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Simple proxy gelf messages to the Sentry.
+/// Flags override env variables and config file keys with the same names.
+#[derive(StructOpt, Debug)]
+pub struct Opts {
+    /// Path to the toml or yaml config file, env variables override its keys
+    #[structopt(long, env = "GTSA_CONFIG", parse(from_os_str))]
+    pub config: Option<PathBuf>,
+    /// Validate configuration and exit
+    #[structopt(long)]
+    pub check_config: bool,
+
+    /// Do not accept gelf messages over udp
+    #[structopt(long)]
+    no_udp: bool,
+    /// Do not accept gelf messages over tcp
+    #[structopt(long)]
+    no_tcp: bool,
+    /// Accept gelf messages over http, it is disabled by default
+    #[structopt(long)]
+    http: bool,
+
+    /// Name of the application
+    #[structopt(long)]
+    system: Option<String>,
+    /// Log filter, e.g. info or warn,gtsa::sentry=debug
+    #[structopt(long)]
+    log_level: Option<String>,
+    /// Log format: text or json
+    #[structopt(long)]
+    log_format: Option<String>,
+    /// Udp address
+    #[structopt(long)]
+    udp_addr: Option<String>,
+    /// Tcp address
+    #[structopt(long)]
+    tcp_addr: Option<String>,
+    /// Http address, gelf messages are accepted with POST /gelf
+    #[structopt(long)]
+    http_addr: Option<String>,
+    /// Admin http address with metrics and health probes
+    #[structopt(long)]
+    admin_addr: Option<String>,
+    /// Threads for decoding and encoding json messages
+    #[structopt(long)]
+    reader_threads: Option<String>,
+    /// Gelf parse mode: lenient or strict
+    #[structopt(long)]
+    gelf_parse_mode: Option<String>,
+    /// Threads for unpacking gz and zlib messages
+    #[structopt(long)]
+    unpacker_threads: Option<String>,
+    /// Maximum chunked udp messages in assembling
+    #[structopt(long)]
+    max_parallel_chunks: Option<String>,
+    /// Maximum chunked udp messages in assembling from one host, 0 means no limit
+    #[structopt(long)]
+    max_parallel_chunks_per_source: Option<String>,
+    /// Time to receive all chunks of the udp message in milliseconds
+    #[structopt(long)]
+    chunk_timeout_ms: Option<String>,
+    /// Maximum size of the assembled chunked udp message
+    #[structopt(long)]
+    max_chunked_message_bytes: Option<String>,
+    /// Maximum udp packets in processing
+    #[structopt(long)]
+    udp_max_in_flight: Option<String>,
+    /// Maximum size of the udp datagram, larger datagrams are dropped
+    #[structopt(long)]
+    udp_recv_buffer_size: Option<String>,
+    /// Count of the udp sockets, which are bound with SO_REUSEPORT and read on their own threads
+    #[structopt(long)]
+    udp_sockets: Option<String>,
+    /// SO_RCVBUF of the udp sockets in bytes
+    #[structopt(long)]
+    udp_socket_recv_buffer: Option<String>,
+    /// Maximum size of the tcp frame
+    #[structopt(long)]
+    tcp_max_frame_size: Option<String>,
+    /// Maximum size of the http request body
+    #[structopt(long)]
+    http_max_body_size: Option<String>,
+    /// Split tcp frames by new line too
+    #[structopt(long)]
+    tcp_split_newline: bool,
+    /// PEM certificate chain of the tcp tls
+    #[structopt(long)]
+    tcp_tls_cert: Option<String>,
+    /// PEM private key of the tcp tls
+    #[structopt(long)]
+    tcp_tls_key: Option<String>,
+    /// PEM CA certificates of the tcp tls clients
+    #[structopt(long)]
+    tcp_tls_client_ca: Option<String>,
+    /// Time to finish the tcp tls handshake in milliseconds
+    #[structopt(long)]
+    tcp_tls_handshake_timeout_ms: Option<String>,
+    /// Default sentry dsn
+    #[structopt(long)]
+    dsn: Option<String>,
+    /// Json array of the sentry routes
+    #[structopt(long)]
+    sentry_routes: Option<String>,
+    /// Send events to the deprecated store endpoint
+    #[structopt(long)]
+    sentry_legacy_store: bool,
+    /// Retries of the failed sentry request
+    #[structopt(long)]
+    sentry_max_retries: Option<String>,
+    /// Initial pause between retries in milliseconds
+    #[structopt(long)]
+    sentry_retry_backoff_ms: Option<String>,
+    /// Maximum pause between retries in milliseconds
+    #[structopt(long)]
+    sentry_retry_max_backoff_ms: Option<String>,
+    /// Directory of the on-disk spool
+    #[structopt(long)]
+    sentry_spool_dir: Option<String>,
+    /// Maximum size of the on-disk spool
+    #[structopt(long)]
+    sentry_spool_max_bytes: Option<String>,
+    /// Maximum count of events waiting for sending
+    #[structopt(long)]
+    sentry_queue_size: Option<String>,
+    /// Maximum count of parallel sentry requests
+    #[structopt(long)]
+    sentry_max_in_flight: Option<String>,
+    /// What to do when the queue is full: drop_newest, drop_oldest or block
+    #[structopt(long)]
+    sentry_overflow_policy: Option<String>,
+    /// Comma separated gelf additional fields, which are sent as sentry tags
+    #[structopt(long)]
+    sentry_tags: Option<String>,
+    /// Json list of the rules, which map gelf fields to the sentry event
+    #[structopt(long)]
+    sentry_mapping: Option<String>,
+    /// Time to drain in-flight events after SIGTERM or SIGINT in milliseconds
+    #[structopt(long)]
+    shutdown_timeout_ms: Option<String>,
+    /// Time in milliseconds, after which not answering actor fails the liveness probe
+    #[structopt(long)]
+    liveness_timeout_ms: Option<String>,
+}
+
+impl Opts {
+    /// Passed settings by the names of their env variables
+    pub fn overrides(&self) -> HashMap<&'static str, String> {
+        let values = vec![
+            ("SYSTEM", self.system.clone()),
+            ("LOG_LEVEL", self.log_level.clone()),
+            ("LOG_FORMAT", self.log_format.clone()),
+            ("UDP_ADDR", self.udp_addr.clone()),
+            ("TCP_ADDR", self.tcp_addr.clone()),
+            ("HTTP_ADDR", self.http_addr.clone()),
+            ("ADMIN_ADDR", self.admin_addr.clone()),
+            ("READER_THREADS", self.reader_threads.clone()),
+            ("GELF_PARSE_MODE", self.gelf_parse_mode.clone()),
+            ("UNPACKER_THREADS", self.unpacker_threads.clone()),
+            ("MAX_PARALLEL_CHUNKS", self.max_parallel_chunks.clone()),
+            (
+                "MAX_PARALLEL_CHUNKS_PER_SOURCE",
+                self.max_parallel_chunks_per_source.clone(),
+            ),
+            ("CHUNK_TIMEOUT_MS", self.chunk_timeout_ms.clone()),
+            (
+                "MAX_CHUNKED_MESSAGE_BYTES",
+                self.max_chunked_message_bytes.clone(),
+            ),
+            ("UDP_MAX_IN_FLIGHT", self.udp_max_in_flight.clone()),
+            ("UDP_RECV_BUFFER_SIZE", self.udp_recv_buffer_size.clone()),
+            ("UDP_SOCKETS", self.udp_sockets.clone()),
+            (
+                "UDP_SOCKET_RECV_BUFFER",
+                self.udp_socket_recv_buffer.clone(),
+            ),
+            ("TCP_MAX_FRAME_SIZE", self.tcp_max_frame_size.clone()),
+            ("HTTP_MAX_BODY_SIZE", self.http_max_body_size.clone()),
+            ("TCP_TLS_CERT", self.tcp_tls_cert.clone()),
+            ("TCP_TLS_KEY", self.tcp_tls_key.clone()),
+            ("TCP_TLS_CLIENT_CA", self.tcp_tls_client_ca.clone()),
+            (
+                "TCP_TLS_HANDSHAKE_TIMEOUT_MS",
+                self.tcp_tls_handshake_timeout_ms.clone(),
+            ),
+            ("SENTRY_DSN", self.dsn.clone()),
+            ("SENTRY_ROUTES", self.sentry_routes.clone()),
+            ("SENTRY_MAX_RETRIES", self.sentry_max_retries.clone()),
+            (
+                "SENTRY_RETRY_BACKOFF_MS",
+                self.sentry_retry_backoff_ms.clone(),
+            ),
+            (
+                "SENTRY_RETRY_MAX_BACKOFF_MS",
+                self.sentry_retry_max_backoff_ms.clone(),
+            ),
+            ("SENTRY_SPOOL_DIR", self.sentry_spool_dir.clone()),
+            (
+                "SENTRY_SPOOL_MAX_BYTES",
+                self.sentry_spool_max_bytes.clone(),
+            ),
+            ("SENTRY_QUEUE_SIZE", self.sentry_queue_size.clone()),
+            ("SENTRY_MAX_IN_FLIGHT", self.sentry_max_in_flight.clone()),
+            (
+                "SENTRY_OVERFLOW_POLICY",
+                self.sentry_overflow_policy.clone(),
+            ),
+            ("SENTRY_TAGS", self.sentry_tags.clone()),
+            ("SENTRY_MAPPING", self.sentry_mapping.clone()),
+            ("SHUTDOWN_TIMEOUT_MS", self.shutdown_timeout_ms.clone()),
+            ("LIVENESS_TIMEOUT_MS", self.liveness_timeout_ms.clone()),
+        ];
+        let flags = vec![
+            ("UDP_ENABLED", self.no_udp, "false"),
+            ("TCP_ENABLED", self.no_tcp, "false"),
+            ("HTTP_ENABLED", self.http, "true"),
+            ("TCP_SPLIT_NEWLINE", self.tcp_split_newline, "true"),
+            ("SENTRY_LEGACY_STORE", self.sentry_legacy_store, "true"),
+        ];
+
+        values
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?)))
+            .chain(
+                flags
+                    .into_iter()
+                    .filter(|(_, passed, _)| *passed)
+                    .map(|(key, _, value)| (key, value.to_string())),
+            )
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod arguments {
+    use super::*;
+
+    #[test]
+    fn test_overrides() {
+        let opts = Opts::from_iter(&[
+            "gtsa",
+            "--dsn",
+            "https://key@sentry.io/1",
+            "--reader-threads",
+            "4",
+            "--no-tcp",
+            "--http",
+            "--tcp-split-newline",
+        ]);
+        let overrides = opts.overrides();
+
+        assert_eq!(overrides.len(), 5);
+        assert_eq!(overrides["HTTP_ENABLED"], "true");
+        assert_eq!(overrides["SENTRY_DSN"], "https://key@sentry.io/1");
+        assert_eq!(overrides["READER_THREADS"], "4");
+        assert_eq!(overrides["TCP_ENABLED"], "false");
+        assert_eq!(overrides["TCP_SPLIT_NEWLINE"], "true");
+        assert!(!overrides.contains_key("UDP_ENABLED"));
+    }
+
+    #[test]
+    fn test_unknown_flag() {
+        assert!(Opts::from_iter_safe(&["gtsa", "--udp"]).is_err());
+    }
+}
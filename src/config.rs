@@ -0,0 +1,577 @@
+use crate::gelf::chunking::ChunkPolicy;
+use crate::gelf::gelf_reader::ParseMode;
+use crate::gelf::tls::{new_tls_acceptor, TlsSettings};
+use crate::gelf::udp_acceptor::{UdpSettings, MAX_UDP_PAYLOAD};
+use crate::logging::{LogFilter, LogFormat};
+use crate::sentry::dsn::Dsn;
+use crate::sentry::mapping::{validate, EventMapping, FieldRule, MappingTarget};
+use crate::sentry::queue::{OverflowPolicy, QueuePolicy};
+use crate::sentry::routing::SentryRoute;
+use crate::sentry::sentry_processor::SentryEndpoint;
+use crate::sentry::transport::RetryPolicy;
+use serde_json::{Map, Value};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Keys of the config file.
+/// Every key could be overridden by the env variable with uppercased name, e.g. `UDP_ADDR`.
+pub const KEYS: &[&str] = &[
+    "system",
+    "log_level",
+    "log_format",
+    "udp_enabled",
+    "tcp_enabled",
+    "http_enabled",
+    "udp_addr",
+    "tcp_addr",
+    "http_addr",
+    "admin_addr",
+    "reader_threads",
+    "gelf_parse_mode",
+    "unpacker_threads",
+    "max_parallel_chunks",
+    "max_parallel_chunks_per_source",
+    "chunk_timeout_ms",
+    "max_chunked_message_bytes",
+    "udp_max_in_flight",
+    "udp_recv_buffer_size",
+    "udp_sockets",
+    "udp_socket_recv_buffer",
+    "tcp_max_frame_size",
+    "tcp_split_newline",
+    "tcp_tls_cert",
+    "tcp_tls_key",
+    "tcp_tls_client_ca",
+    "tcp_tls_handshake_timeout_ms",
+    "http_max_body_size",
+    "sentry_dsn",
+    "sentry_routes",
+    "sentry_legacy_store",
+    "sentry_max_retries",
+    "sentry_retry_backoff_ms",
+    "sentry_retry_max_backoff_ms",
+    "sentry_spool_dir",
+    "sentry_spool_max_bytes",
+    "sentry_queue_size",
+    "sentry_max_in_flight",
+    "sentry_overflow_policy",
+    "sentry_tags",
+    "sentry_mapping",
+    "shutdown_timeout_ms",
+    "liveness_timeout_ms",
+];
+
+/// Problem of the configuration with the name of its key
+#[derive(Debug, PartialEq)]
+pub struct ConfigError {
+    pub key: String,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn new(key: &str, message: impl Into<String>) -> ConfigError {
+        ConfigError {
+            key: key.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+/// Validated settings of gtsa
+pub struct Config {
+    pub system: String,
+    pub log_filter: LogFilter,
+    pub log_format: LogFormat,
+    pub udp_enabled: bool,
+    pub tcp_enabled: bool,
+    pub http_enabled: bool,
+    pub udp_addr: String,
+    pub tcp_addr: String,
+    pub http_addr: String,
+    pub admin_addr: String,
+    pub reader_threads: usize,
+    pub gelf_parse_mode: ParseMode,
+    pub unpacker_threads: usize,
+    pub udp_settings: UdpSettings,
+    pub tcp_max_frame_size: usize,
+    pub tcp_split_newline: bool,
+    pub tcp_tls: Option<TlsSettings>,
+    pub http_max_body_size: usize,
+    pub sentry_dsn: Option<Dsn>,
+    pub sentry_routes: Vec<SentryRoute>,
+    pub sentry_endpoint: SentryEndpoint,
+    pub sentry_retry_policy: RetryPolicy,
+    pub sentry_spool_dir: Option<String>,
+    pub sentry_spool_max_bytes: u64,
+    pub sentry_queue_policy: QueuePolicy,
+    pub sentry_event_mapping: EventMapping,
+    pub shutdown_timeout: Duration,
+    pub liveness_timeout: Duration,
+}
+
+impl Config {
+    /// Load config file, if it passed, and apply overrides over it.
+    /// All problems of the configuration are returned at once.
+    pub fn load<F>(path: Option<&Path>, overrides: F) -> Result<Config, Vec<ConfigError>>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let file = match path {
+            Some(path) => read_config_file(path).map_err(|e| vec![e])?,
+            None => Map::new(),
+        };
+        Config::from_sources(file, overrides)
+    }
+
+    fn from_sources<F>(file: Map<String, Value>, overrides: F) -> Result<Config, Vec<ConfigError>>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut source = Source {
+            errors: file
+                .keys()
+                .filter(|key| !KEYS.contains(&key.as_str()))
+                .map(|key| ConfigError::new(key, "unknown key"))
+                .collect(),
+            file,
+            overrides,
+        };
+
+        let tcp_tls = source.tls();
+        let config = Config {
+            system: source.parse("system", "Gelf Mover".to_string()),
+            log_filter: source.parse("log_level", LogFilter::default()),
+            log_format: source.parse("log_format", LogFormat::Text),
+            udp_enabled: source.parse("udp_enabled", true),
+            tcp_enabled: source.parse("tcp_enabled", true),
+            http_enabled: source.parse("http_enabled", false),
+            udp_addr: source.addr("udp_addr", "0.0.0.0:8080"),
+            tcp_addr: source.addr("tcp_addr", "0.0.0.0:8081"),
+            http_addr: source.addr("http_addr", "0.0.0.0:8082"),
+            admin_addr: source.addr("admin_addr", "0.0.0.0:8083"),
+            reader_threads: source.positive("reader_threads", 1),
+            gelf_parse_mode: source.parse("gelf_parse_mode", ParseMode::Strict),
+            unpacker_threads: source.positive("unpacker_threads", 1),
+            udp_settings: UdpSettings {
+                max_in_flight: source.positive("udp_max_in_flight", 1024),
+                recv_buffer_size: source.positive("udp_recv_buffer_size", MAX_UDP_PAYLOAD),
+                sockets: source.positive("udp_sockets", 1),
+                socket_recv_buffer: source.optional("udp_socket_recv_buffer"),
+                chunk_policy: ChunkPolicy {
+                    timeout: Duration::from_millis(source.positive("chunk_timeout_ms", 5000) as u64),
+                    max_messages: source.positive("max_parallel_chunks", 500),
+                    max_messages_per_source: source.parse("max_parallel_chunks_per_source", 0),
+                    max_message_bytes: source.positive("max_chunked_message_bytes", 1024 * 1024),
+                },
+            },
+            tcp_max_frame_size: source.positive("tcp_max_frame_size", 1024 * 1024),
+            tcp_split_newline: source.parse("tcp_split_newline", false),
+            tcp_tls,
+            http_max_body_size: source.positive("http_max_body_size", 1024 * 1024),
+            sentry_dsn: source.optional("sentry_dsn"),
+            sentry_routes: source.routes(),
+            sentry_endpoint: match source.parse("sentry_legacy_store", false) {
+                true => SentryEndpoint::Store,
+                false => SentryEndpoint::Envelope,
+            },
+            sentry_retry_policy: RetryPolicy {
+                max_retries: source.parse("sentry_max_retries", 3),
+                initial_backoff: Duration::from_millis(
+                    source.parse("sentry_retry_backoff_ms", 500),
+                ),
+                max_backoff: Duration::from_millis(
+                    source.parse("sentry_retry_max_backoff_ms", 30000),
+                ),
+            },
+            sentry_spool_dir: source.optional("sentry_spool_dir"),
+            sentry_spool_max_bytes: source.parse("sentry_spool_max_bytes", 64 * 1024 * 1024),
+            sentry_queue_policy: QueuePolicy {
+                capacity: source.positive("sentry_queue_size", 10000),
+                max_in_flight: source.positive("sentry_max_in_flight", 32),
+                overflow: source.parse("sentry_overflow_policy", OverflowPolicy::DropNewest),
+            },
+            sentry_event_mapping: source.event_mapping(),
+            shutdown_timeout: Duration::from_millis(source.parse("shutdown_timeout_ms", 30000)),
+            liveness_timeout: Duration::from_millis(
+                source.positive("liveness_timeout_ms", 30000) as u64
+            ),
+        };
+
+        if config.sentry_dsn.is_none()
+            && config.sentry_routes.is_empty()
+            && !source.has_error("sentry_dsn")
+            && !source.has_error("sentry_routes")
+        {
+            source.error("sentry_dsn", "sentry_dsn or sentry_routes must be passed");
+        }
+
+        if config.udp_settings.recv_buffer_size > MAX_UDP_PAYLOAD {
+            source.error(
+                "udp_recv_buffer_size",
+                format!("value must not be greater than {}", MAX_UDP_PAYLOAD),
+            );
+        }
+
+        if !config.udp_enabled && !config.tcp_enabled && !config.http_enabled {
+            source.error("udp_enabled", "at least one listener must be enabled");
+        }
+
+        match source.errors.is_empty() {
+            true => Ok(config),
+            false => Err(source.errors),
+        }
+    }
+}
+
+/// Read toml or yaml config file, format is selected by the file extension
+fn read_config_file(path: &Path) -> Result<Map<String, Value>, ConfigError> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        ConfigError::new(
+            "config",
+            format!("failed to read {}: {}", path.display(), e),
+        )
+    })?;
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    parse_config(&content, extension)
+}
+
+fn parse_config(content: &str, extension: &str) -> Result<Map<String, Value>, ConfigError> {
+    let value: Value = match extension {
+        "toml" => toml::from_str(content).map_err(|e| ConfigError::new("config", e.to_string()))?,
+        "yaml" | "yml" => {
+            serde_yaml::from_str(content).map_err(|e| ConfigError::new("config", e.to_string()))?
+        }
+        extension => {
+            return Err(ConfigError::new(
+                "config",
+                format!(
+                    "config file must be toml, yaml or yml, but {} is passed",
+                    extension
+                ),
+            ))
+        }
+    };
+
+    match value {
+        Value::Object(map) => Ok(map),
+        Value::Null => Ok(Map::new()),
+        _ => Err(ConfigError::new("config", "config file must be a table")),
+    }
+}
+
+/// Config values with overrides, which collects problems of every key
+struct Source<F> {
+    file: Map<String, Value>,
+    overrides: F,
+    errors: Vec<ConfigError>,
+}
+
+impl<F> Source<F>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    fn raw(&self, key: &str) -> Option<Value> {
+        (self.overrides)(&key.to_uppercase())
+            .map(Value::String)
+            .or_else(|| self.file.get(key).cloned())
+            .filter(|value| !value.is_null())
+    }
+
+    fn optional<T>(&mut self, key: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let raw = match self.raw(key)? {
+            Value::String(raw) => raw,
+            value @ Value::Number(_) | value @ Value::Bool(_) => value.to_string(),
+            _ => {
+                self.error(key, "value must be a string, number or boolean");
+                return None;
+            }
+        };
+
+        match raw.parse() {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.error(key, format!("invalid value {}: {}", raw, e));
+                None
+            }
+        }
+    }
+
+    fn parse<T>(&mut self, key: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.optional(key).unwrap_or(default)
+    }
+
+    fn positive(&mut self, key: &str, default: usize) -> usize {
+        match self.parse(key, default) {
+            0 => {
+                self.error(key, "value must be greater than zero");
+                default
+            }
+            value => value,
+        }
+    }
+
+    fn addr(&mut self, key: &str, default: &str) -> String {
+        let addr: String = self.parse(key, default.to_string());
+        let has_port =
+            matches!(addr.rsplit_once(':'), Some((_, port)) if port.parse::<u16>().is_ok());
+        if !has_port {
+            self.error(
+                key,
+                format!("address must be host:port, but {} is passed", addr),
+            );
+        }
+        addr
+    }
+
+    /// List of strings, which is passed as an array or as a comma separated string
+    fn list(&mut self, key: &str) -> Vec<String> {
+        let items = match self.raw(key) {
+            None => return Vec::new(),
+            Some(Value::String(items)) => items.split(',').map(str::to_string).collect(),
+            Some(items) => match serde_json::from_value::<Vec<String>>(items) {
+                Ok(items) => items,
+                Err(_) => {
+                    self.error(
+                        key,
+                        "value must be an array of strings or a comma separated string",
+                    );
+                    return Vec::new();
+                }
+            },
+        };
+        items
+            .iter()
+            .map(|item| item.trim())
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Mapping rules of the sentry events, `sentry_tags` are the rules with the tags target
+    fn event_mapping(&mut self) -> EventMapping {
+        let mut rules: Vec<FieldRule> = self
+            .list("sentry_tags")
+            .iter()
+            .map(|tag| {
+                FieldRule::new(
+                    &format!("_{}", tag.trim_start_matches('_')),
+                    MappingTarget::Tags,
+                )
+            })
+            .collect();
+
+        let mapping = match self.raw("sentry_mapping") {
+            None => Ok(Vec::new()),
+            Some(Value::String(mapping)) => serde_json::from_str(&mapping),
+            Some(mapping) => serde_json::from_value(mapping),
+        };
+        match mapping {
+            Ok(mapping) => rules.extend(mapping),
+            Err(e) => self.error("sentry_mapping", format!("invalid mapping: {}", e)),
+        }
+        for rule in &rules {
+            if let Err(e) = validate(rule) {
+                self.error("sentry_mapping", e.to_string());
+            }
+        }
+        EventMapping::new(rules)
+    }
+
+    fn routes(&mut self) -> Vec<SentryRoute> {
+        let routes = match self.raw("sentry_routes") {
+            None => return Vec::new(),
+            Some(Value::String(routes)) => serde_json::from_str(&routes),
+            Some(routes) => serde_json::from_value(routes),
+        };
+        routes.unwrap_or_else(|e| {
+            self.error("sentry_routes", format!("invalid routes: {}", e));
+            Vec::new()
+        })
+    }
+
+    fn tls(&mut self) -> Option<TlsSettings> {
+        let settings = match (self.optional("tcp_tls_cert"), self.optional("tcp_tls_key")) {
+            (Some(cert_path), Some(key_path)) => TlsSettings {
+                cert_path,
+                key_path,
+                client_ca_path: self.optional("tcp_tls_client_ca"),
+                handshake_timeout: Duration::from_millis(
+                    self.positive("tcp_tls_handshake_timeout_ms", 10000) as u64,
+                ),
+            },
+            (None, None) => return None,
+            (None, Some(_)) => {
+                self.error(
+                    "tcp_tls_cert",
+                    "tcp_tls_cert must be passed with tcp_tls_key",
+                );
+                return None;
+            }
+            (Some(_), None) => {
+                self.error(
+                    "tcp_tls_key",
+                    "tcp_tls_key must be passed with tcp_tls_cert",
+                );
+                return None;
+            }
+        };
+
+        if let Err(e) = new_tls_acceptor(&settings) {
+            self.error("tcp_tls_cert", e.to_string());
+        }
+        Some(settings)
+    }
+
+    fn error(&mut self, key: &str, message: impl Into<String>) {
+        self.errors.push(ConfigError::new(key, message))
+    }
+
+    fn has_error(&self, key: &str) -> bool {
+        self.errors.iter().any(|e| e.key == key)
+    }
+}
+
+#[cfg(test)]
+mod loader {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(
+        content: &str,
+        extension: &str,
+        env: &[(&str, &str)],
+    ) -> Result<Config, Vec<ConfigError>> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Config::from_sources(parse_config(content, extension).unwrap(), |key| {
+            env.get(key).cloned()
+        })
+    }
+
+    #[test]
+    fn test_toml_with_env_overrides() {
+        let config = load(
+            r#"
+            udp_addr = "127.0.0.1:12201"
+            reader_threads = 4
+            sentry_overflow_policy = "block"
+            sentry_tags = ["_service", "region"]
+
+            [[sentry_mapping]]
+            field = "_env"
+            target = "environment"
+
+            [[sentry_routes]]
+            field = "_service"
+            value = "billing"
+            dsn = "https://key@sentry.io/1"
+            "#,
+            "toml",
+            &[("READER_THREADS", "8"), ("SENTRY_LEGACY_STORE", "true")],
+        )
+        .unwrap_or_else(|e| panic!("{:?}", e));
+
+        assert_eq!(config.udp_addr, "127.0.0.1:12201");
+        assert_eq!(config.tcp_addr, "0.0.0.0:8081");
+        assert_eq!(config.reader_threads, 8);
+        assert_eq!(config.sentry_endpoint, SentryEndpoint::Store);
+        assert_eq!(config.sentry_queue_policy.overflow, OverflowPolicy::Block);
+        assert_eq!(config.sentry_routes.len(), 1);
+        assert!(config.sentry_dsn.is_none());
+        let fields: Vec<String> = config
+            .sentry_event_mapping
+            .rules
+            .iter()
+            .map(|rule| format!("{} {}", rule.field, rule.target))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                "_service tags",
+                "_region tags",
+                "_env environment",
+                "_environment environment",
+                "_release release"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_yaml() {
+        let config = load(
+            "sentry_dsn: https://key@sentry.io/1\ntcp_split_newline: true\n",
+            "yml",
+            &[("SENTRY_TAGS", "_service, env,")],
+        )
+        .unwrap_or_else(|e| panic!("{:?}", e));
+
+        assert!(config.tcp_split_newline);
+        assert_eq!(config.sentry_event_mapping.rules[1].field, "_env");
+        assert_eq!(
+            config.sentry_dsn.unwrap().to_string(),
+            "https://key@sentry.io/1"
+        );
+        assert!(parse_config("", "ini").is_err());
+    }
+
+    #[test]
+    fn test_all_errors_reported() {
+        let errors = match load(
+            r#"
+            udp_addr = "localhost"
+            udp_enabled = false
+            tcp_enabled = false
+            http_enabled = false
+            reader_threads = 0
+            udp_recv_buffer_size = 70000
+            unknown = 1
+            tcp_tls_key = "key.pem"
+            sentry_mapping = '[{"field": "_env", "target": "environment", "rename": "env"}]'
+            "#,
+            "toml",
+            &[
+                ("SENTRY_DSN", "https://sentry.io/1"),
+                ("SENTRY_MAX_RETRIES", "many"),
+            ],
+        ) {
+            Ok(_) => panic!("config must be invalid"),
+            Err(errors) => errors,
+        };
+
+        let mut keys: Vec<&str> = errors.iter().map(|e| e.key.as_str()).collect();
+        keys.sort_unstable();
+        assert_eq!(
+            keys,
+            vec![
+                "reader_threads",
+                "sentry_dsn",
+                "sentry_mapping",
+                "sentry_max_retries",
+                "tcp_tls_cert",
+                "udp_addr",
+                "udp_enabled",
+                "udp_recv_buffer_size",
+                "unknown"
+            ]
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::time::{Duration, Instant};
+
+/// Magic bytes of the chunked gelf message
+const CHUNK_MAGIC: [u8; 2] = [0x1e, 0x0f];
+/// Size of the chunk header: magic bytes, message id, sequence number and sequence count
+const CHUNK_HEADER_SIZE: usize = 12;
+/// Maximum count of chunks in the message by the gelf spec
+pub const MAX_CHUNKS: u8 = 128;
+
+/// Limits of the chunked messages assembling
+#[derive(Clone, Copy, Debug)]
+pub struct ChunkPolicy {
+    /// Incomplete message is dropped, when its chunks are not received in this time
+    pub timeout: Duration,
+    /// Maximum count of the messages in assembling by one udp socket, the oldest one is evicted on overflow
+    pub max_messages: usize,
+    /// Maximum count of the messages in assembling from one host, 0 means no limit.
+    /// The oldest message of the host is evicted on overflow.
+    pub max_messages_per_source: usize,
+    /// Maximum size of the assembled message payload
+    pub max_message_bytes: usize,
+}
+
+/// Id of the chunked message, 8 bytes are read as big endian integer
+pub type MessageId = u64;
+
+pub fn is_chunk(buf: &[u8]) -> bool {
+    buf.starts_with(&CHUNK_MAGIC)
+}
+
+/// Chunk of the udp gelf message.
+/// Header is `0x1e 0x0f`, 8 bytes of the message id, sequence number and sequence count.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessageChunk {
+    pub message_id: MessageId,
+    pub sequence_number: u8,
+    pub sequence_count: u8,
+    pub payload: Vec<u8>,
+}
+
+impl MessageChunk {
+    pub fn parse(buf: &[u8]) -> Result<MessageChunk, ChunkError> {
+        if buf.len() < CHUNK_HEADER_SIZE || !is_chunk(buf) {
+            return Err(ChunkError::TooShort(buf.len()));
+        }
+        let mut message_id = [0; 8];
+        message_id.copy_from_slice(&buf[2..10]);
+        let sequence_number = buf[10];
+        let sequence_count = buf[11];
+
+        if sequence_count == 0 || sequence_count > MAX_CHUNKS {
+            return Err(ChunkError::InvalidCount(sequence_count));
+        }
+        if sequence_number >= sequence_count {
+            return Err(ChunkError::InvalidNumber(sequence_number, sequence_count));
+        }
+
+        Ok(MessageChunk {
+            message_id: MessageId::from_be_bytes(message_id),
+            sequence_number,
+            sequence_count,
+            payload: buf[CHUNK_HEADER_SIZE..].to_vec(),
+        })
+    }
+}
+
+/// Chunks of the message, which are received so far
+#[derive(Debug)]
+pub struct ChunkedMessage {
+    pub started_at: Instant,
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+    bytes: usize,
+    max_bytes: usize,
+}
+
+impl ChunkedMessage {
+    pub fn new(sequence_count: u8, max_bytes: usize) -> ChunkedMessage {
+        ChunkedMessage {
+            started_at: Instant::now(),
+            chunks: vec![None; sequence_count as usize],
+            received: 0,
+            bytes: 0,
+            max_bytes,
+        }
+    }
+
+    /// Add chunk to the message. Returns true, when all chunks are received.
+    pub fn add(&mut self, chunk: MessageChunk) -> Result<bool, ChunkError> {
+        if chunk.sequence_count as usize != self.chunks.len() {
+            return Err(ChunkError::CountMismatch(
+                chunk.sequence_count,
+                self.chunks.len() as u8,
+            ));
+        }
+        let slot = &mut self.chunks[chunk.sequence_number as usize];
+        if slot.is_some() {
+            return Err(ChunkError::Duplicate(chunk.sequence_number));
+        }
+        if self.bytes + chunk.payload.len() > self.max_bytes {
+            return Err(ChunkError::TooLarge(self.max_bytes));
+        }
+        self.bytes += chunk.payload.len();
+        *slot = Some(chunk.payload);
+        self.received += 1;
+        Ok(self.is_complete())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.received == self.chunks.len()
+    }
+
+    /// Concatenate payloads of the chunks in the order of their sequence numbers
+    pub fn assemble(self) -> Vec<u8> {
+        self.chunks.into_iter().flatten().flatten().collect()
+    }
+}
+
+/// Error, which returned when chunk is malformed or does not fit the message
+#[derive(Debug, PartialEq)]
+pub enum ChunkError {
+    TooShort(usize),
+    InvalidCount(u8),
+    InvalidNumber(u8, u8),
+    CountMismatch(u8, u8),
+    Duplicate(u8),
+    TooLarge(usize),
+}
+
+impl Display for ChunkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            ChunkError::TooShort(len) => write!(
+                f,
+                "chunk must have {} bytes of header, but {} bytes are received",
+                CHUNK_HEADER_SIZE, len
+            ),
+            ChunkError::InvalidCount(count) => write!(
+                f,
+                "chunks count must be from 1 to {}, but {} is received",
+                MAX_CHUNKS, count
+            ),
+            ChunkError::InvalidNumber(number, count) => write!(
+                f,
+                "chunk number must be less than count {}, but {} is received",
+                count, number
+            ),
+            ChunkError::CountMismatch(count, expected) => write!(
+                f,
+                "chunks count of the message is {}, but {} is received",
+                expected, count
+            ),
+            ChunkError::Duplicate(number) => write!(f, "chunk {} is already received", number),
+            ChunkError::TooLarge(max_bytes) => {
+                write!(f, "chunked message is larger than {} bytes", max_bytes)
+            }
+        }
+    }
+}
+
+impl Error for ChunkError {}
+
+#[cfg(test)]
+mod parser {
+    use super::*;
+
+    fn chunk(id: u8, number: u8, count: u8, payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0x1e, 0x0f, 0, 0, 0, 0, 0, 0, 0, id, number, count];
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    #[test]
+    fn test_parse() {
+        let parsed = MessageChunk::parse(&chunk(7, 1, 2, b"test")).unwrap();
+        assert_eq!(parsed.message_id, 7);
+        assert_eq!(parsed.sequence_number, 1);
+        assert_eq!(parsed.sequence_count, 2);
+        assert_eq!(parsed.payload, b"test".to_vec());
+
+        let mut id = chunk(0, 0, 1, b"");
+        id[2..10].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(
+            MessageChunk::parse(&id).unwrap().message_id,
+            0x0102_0304_0506_0708
+        );
+
+        assert_eq!(
+            MessageChunk::parse(&[0x1e, 0x0f, 1]),
+            Err(ChunkError::TooShort(3))
+        );
+        assert_eq!(
+            MessageChunk::parse(&chunk(7, 0, 129, b"")),
+            Err(ChunkError::InvalidCount(129))
+        );
+        assert_eq!(
+            MessageChunk::parse(&chunk(7, 0, 0, b"")),
+            Err(ChunkError::InvalidCount(0))
+        );
+        assert_eq!(
+            MessageChunk::parse(&chunk(7, 2, 2, b"")),
+            Err(ChunkError::InvalidNumber(2, 2))
+        );
+    }
+
+    #[test]
+    fn test_assemble() {
+        let parse = |buf: Vec<u8>| MessageChunk::parse(&buf).unwrap();
+        let mut message = ChunkedMessage::new(3, 1024);
+
+        assert_eq!(message.add(parse(chunk(7, 2, 3, b"c"))), Ok(false));
+        assert_eq!(message.add(parse(chunk(7, 0, 3, b"a"))), Ok(false));
+        assert_eq!(
+            message.add(parse(chunk(7, 0, 3, b"x"))),
+            Err(ChunkError::Duplicate(0))
+        );
+        assert_eq!(
+            message.add(parse(chunk(7, 1, 2, b"x"))),
+            Err(ChunkError::CountMismatch(2, 3))
+        );
+        assert_eq!(message.add(parse(chunk(7, 1, 3, b"b"))), Ok(true));
+        assert_eq!(message.assemble(), b"abc".to_vec());
+
+        let mut message = ChunkedMessage::new(2, 6);
+        assert_eq!(message.add(parse(chunk(7, 0, 2, b"abcd"))), Ok(false));
+        assert_eq!(
+            message.add(parse(chunk(7, 1, 2, b"efg"))),
+            Err(ChunkError::TooLarge(6))
+        );
+    }
+}
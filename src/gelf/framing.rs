@@ -0,0 +1,139 @@
+use bytes::BytesMut;
+use std::io::{Error, ErrorKind, Result};
+use tokio_util::codec::Decoder;
+
+/// Decoder, which splits tcp stream to the gelf frames.
+/// Frames are delimited by null byte and optionally by new line.
+#[derive(Clone)]
+pub struct GelfFrameDecoder {
+    max_frame_size: usize,
+    split_on_newline: bool,
+    next_index: usize,
+}
+
+impl GelfFrameDecoder {
+    pub fn new(max_frame_size: usize, split_on_newline: bool) -> Self {
+        GelfFrameDecoder {
+            max_frame_size,
+            split_on_newline,
+            next_index: 0,
+        }
+    }
+
+    fn is_delimiter(&self, b: u8) -> bool {
+        b == 0 || (self.split_on_newline && b == b'\n')
+    }
+
+    fn check_size(&self, size: usize) -> Result<()> {
+        if size > self.max_frame_size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("gelf frame is bigger than {} bytes", self.max_frame_size),
+            ));
+        }
+        Ok(())
+    }
+
+    fn trim_frame<'a>(&self, frame: &'a [u8]) -> &'a [u8] {
+        match frame.split_last() {
+            Some((b'\r', rest)) if self.split_on_newline => rest,
+            _ => frame,
+        }
+    }
+}
+
+impl Decoder for GelfFrameDecoder {
+    type Item = Vec<u8>;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Vec<u8>>> {
+        loop {
+            let delimiter_offset = src[self.next_index..]
+                .iter()
+                .position(|b| self.is_delimiter(*b));
+
+            let delimiter_index = match delimiter_offset {
+                Some(offset) => self.next_index + offset,
+                None => {
+                    self.check_size(src.len())?;
+                    self.next_index = src.len();
+                    return Ok(None);
+                }
+            };
+            self.next_index = 0;
+
+            let frame = src.split_to(delimiter_index + 1);
+            let frame = self.trim_frame(&frame[..delimiter_index]);
+            self.check_size(frame.len())?;
+
+            if !frame.is_empty() {
+                return Ok(Some(frame.to_vec()));
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Vec<u8>>> {
+        if let Some(frame) = self.decode(src)? {
+            return Ok(Some(frame));
+        }
+        self.next_index = 0;
+
+        let frame = src.split();
+        let frame = self.trim_frame(&frame);
+        if frame.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(frame.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod decoder {
+    use super::*;
+
+    #[test]
+    fn test_null_delimiter() {
+        let mut decoder = GelfFrameDecoder::new(1024, false);
+        let mut buf = BytesMut::from(&b"first\0\0second\0thi"[..]);
+
+        assert_eq!(decoder.decode(&mut buf).unwrap(), Some(b"first".to_vec()));
+        assert_eq!(decoder.decode(&mut buf).unwrap(), Some(b"second".to_vec()));
+        assert_eq!(decoder.decode(&mut buf).unwrap(), None);
+
+        buf.extend_from_slice(b"rd\0");
+        assert_eq!(decoder.decode(&mut buf).unwrap(), Some(b"third".to_vec()));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_newline_delimiter() {
+        let mut decoder = GelfFrameDecoder::new(1024, true);
+        let mut buf = BytesMut::from(&b"first\r\nsecond\0last"[..]);
+
+        assert_eq!(decoder.decode(&mut buf).unwrap(), Some(b"first".to_vec()));
+        assert_eq!(decoder.decode(&mut buf).unwrap(), Some(b"second".to_vec()));
+        assert_eq!(decoder.decode(&mut buf).unwrap(), None);
+        assert_eq!(
+            decoder.decode_eof(&mut buf).unwrap(),
+            Some(b"last".to_vec())
+        );
+        assert_eq!(decoder.decode_eof(&mut buf).unwrap(), None);
+
+        let mut decoder = GelfFrameDecoder::new(1024, false);
+        let mut buf = BytesMut::from(&b"first\nsecond\0"[..]);
+        assert_eq!(
+            decoder.decode(&mut buf).unwrap(),
+            Some(b"first\nsecond".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_max_frame_size() {
+        let mut decoder = GelfFrameDecoder::new(4, false);
+        let mut buf = BytesMut::from(&b"test\0"[..]);
+        assert_eq!(decoder.decode(&mut buf).unwrap(), Some(b"test".to_vec()));
+
+        buf.extend_from_slice(b"tests");
+        assert!(decoder.decode(&mut buf).is_err());
+    }
+}
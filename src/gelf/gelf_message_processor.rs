@@ -1,5 +1,5 @@
 use crate::gelf::gelf_reader::GelfDataWrapper;
-use crate::sentry::sentry_processor::SentryEvent;
+use crate::sentry::event::SentryEvent;
 use actix::prelude::*;
 
 /// Message, which contains parsed gelf data
@@ -40,6 +40,7 @@ impl Handler<GelfProcessorMessage> for GelfPrinterActor {
 #[cfg(test)]
 mod processor {
     use super::*;
+    use crate::gelf::gelf_reader::ParseMode;
 
     #[actix_rt::test]
     async fn test_actor() {
@@ -56,6 +57,7 @@ mod processor {
                         "_some_info":"foo",
                         "timestamp":1582213226
                     }"#,
+                    ParseMode::Strict,
                 )
                 .unwrap(),
             ))
@@ -1,8 +1,12 @@
+use crate::health::Ping;
+use crate::metrics::{GELF_COERCIONS, PARSE_FAILURES};
 use actix::prelude::*;
 use serde::de::{Error, Unexpected};
 use serde::{Deserialize, Serialize};
 use serde_json::{Error as JsonError, Map, Result as JsonResult, Value};
+use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Struct, which contains gelf data
 pub struct GelfDataWrapper {
@@ -11,17 +15,32 @@ pub struct GelfDataWrapper {
 
 impl GelfDataWrapper {
     /// Create gelf data wrapper from json slice
-    pub fn from_slice(buf: &[u8]) -> JsonResult<GelfDataWrapper> {
+    pub fn from_slice(buf: &[u8], mode: ParseMode) -> JsonResult<GelfDataWrapper> {
         let data: Map<String, Value> = serde_json::from_slice(buf)?;
 
-        let data = to_gelf(data)?;
+        let data = to_gelf(data, mode)?;
+        data.coercions.iter().for_each(|coercion| {
+            GELF_COERCIONS
+                .with_label_values(&[coercion.split(':').next().unwrap_or_default()])
+                .inc()
+        });
 
         Ok(GelfDataWrapper { data })
     }
 
     /// print gelf data to stdio
     pub fn print(&self) {
-        println!("{}", self.to_string());
+        println!("{}", self);
+    }
+
+    /// Returns a value of gelf field as string.
+    /// Additional fields are passed with `_` prefix, e.g. `_service`.
+    pub fn field(&self, name: &str) -> Option<String> {
+        match self.data.value(name)? {
+            Value::String(s) => Some(s),
+            Value::Null => None,
+            v => Some(v.to_string()),
+        }
     }
 
     /// Returns a GelfData of this `String`'s contents.
@@ -30,9 +49,9 @@ impl GelfDataWrapper {
     }
 }
 
-impl ToString for GelfDataWrapper {
-    fn to_string(&self) -> String {
-        serde_json::to_string(&self.data).unwrap()
+impl Display for GelfDataWrapper {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", serde_json::to_string(&self.data).unwrap())
     }
 }
 
@@ -42,11 +61,52 @@ impl Message for GelfMessage {
     type Result = JsonResult<GelfDataWrapper>;
 }
 
-pub struct GelfReaderActor;
+/// Gelf versions, which are accepted by the strict mode
+const SUPPORTED_VERSIONS: [&str; 2] = ["1.0", "1.1"];
+
+/// How strictly gelf messages are validated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseMode {
+    /// `level` and `timestamp` are required, `level` must be an integer from 0 to 7,
+    /// `version` must be 1.0 or 1.1
+    Strict,
+    /// Spec defaults are used for missing `level` and `timestamp`,
+    /// string levels, named levels, string timestamps and lines are coerced,
+    /// numeric and boolean `facility`, `file` and `full_message` are stringified
+    Lenient,
+}
+
+impl FromStr for ParseMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(ParseMode::Strict),
+            "lenient" => Ok(ParseMode::Lenient),
+            mode => Err(format!(
+                "parse mode must be strict or lenient, but {} is passed",
+                mode
+            )),
+        }
+    }
+}
+
+impl Display for ParseMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            ParseMode::Strict => write!(f, "strict"),
+            ParseMode::Lenient => write!(f, "lenient"),
+        }
+    }
+}
+
+pub struct GelfReaderActor {
+    mode: ParseMode,
+}
 
 impl GelfReaderActor {
-    pub fn new(threads: usize) -> Addr<GelfReaderActor> {
-        SyncArbiter::start(threads, || GelfReaderActor)
+    pub fn new(threads: usize, mode: ParseMode) -> Addr<GelfReaderActor> {
+        SyncArbiter::start(threads, move || GelfReaderActor { mode })
     }
 }
 
@@ -58,40 +118,142 @@ impl Handler<GelfMessage> for GelfReaderActor {
     type Result = JsonResult<GelfDataWrapper>;
 
     fn handle(&mut self, GelfMessage(msg): GelfMessage, _ctx: &mut Self::Context) -> Self::Result {
-        GelfDataWrapper::from_slice(msg.as_slice())
+        GelfDataWrapper::from_slice(msg.as_slice(), self.mode).inspect_err(|_| PARSE_FAILURES.inc())
     }
 }
 
+impl Handler<Ping> for GelfReaderActor {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Ping, _ctx: &mut Self::Context) {}
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GelfData {
     pub host: String,
     pub level: GelfLevel,
     pub short_message: String,
+    pub full_message: Option<String>,
     pub timestamp: f64,
     pub version: String,
+    /// Deprecated fields of gelf 1.0, they are still sent by many libraries
+    pub facility: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u64>,
     pub meta: Map<String, Value>,
     pub mechanism_data: Map<String, Value>,
+    /// Coercions of the lenient mode in the `field: coercion` form
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub coercions: Vec<String>,
 }
 
-fn to_gelf(data: Map<String, Value>) -> JsonResult<GelfData> {
+impl GelfData {
+    /// Returns a value of gelf field.
+    /// Additional fields are passed with `_` prefix, e.g. `_service`.
+    pub fn value(&self, name: &str) -> Option<Value> {
+        let value = match name {
+            "host" => Value::from(self.host.as_str()),
+            "short_message" => Value::from(self.short_message.as_str()),
+            "version" => Value::from(self.version.as_str()),
+            "full_message" => Value::from(self.full_message.as_deref()?),
+            "facility" => Value::from(self.facility.as_deref()?),
+            "file" => Value::from(self.file.as_deref()?),
+            "line" => Value::from(self.line?),
+            _ => match name.strip_prefix('_') {
+                Some(field) => self.meta.get(field)?.clone(),
+                None => self.mechanism_data.get(name)?.clone(),
+            },
+        };
+        Some(value)
+    }
+
+    /// Removes a value of gelf field, required fields of gelf are kept.
+    /// Additional fields are passed with `_` prefix, e.g. `_service`.
+    pub fn remove(&mut self, name: &str) {
+        match name {
+            "full_message" => self.full_message = None,
+            "facility" => self.facility = None,
+            "file" => self.file = None,
+            "line" => self.line = None,
+            _ => {
+                match name.strip_prefix('_') {
+                    Some(field) => self.meta.remove(field),
+                    None => self.mechanism_data.remove(name),
+                };
+            }
+        }
+    }
+}
+
+fn to_gelf(data: Map<String, Value>, mode: ParseMode) -> JsonResult<GelfData> {
     let mut meta = Map::new();
     let mut mechanism_data = Map::new();
-    let gelf_fields: [String; 5] = [
+    let gelf_fields: [String; 9] = [
         "host".to_string(),
         "level".to_string(),
         "short_message".to_string(),
+        "full_message".to_string(),
         "timestamp".to_string(),
         "version".to_string(),
+        "facility".to_string(),
+        "file".to_string(),
+        "line".to_string(),
     ];
 
     data.iter().for_each(|(k, v)| {
-        match k.split_at(1) {
-            ("_", field) => meta.insert(field.to_string(), v.to_owned()),
-            (_, _) if !gelf_fields.contains(k) => mechanism_data.insert(k.to_owned(), v.to_owned()),
-            _ => None,
+        match k.strip_prefix('_') {
+            Some(field) => meta.insert(field.to_string(), v.to_owned()),
+            None if !gelf_fields.contains(k) => mechanism_data.insert(k.to_owned(), v.to_owned()),
+            None => None,
         };
     });
 
+    let mut coercions = Vec::new();
+    let (level, timestamp) = match mode {
+        ParseMode::Strict => (
+            data.get("level")
+                .ok_or_else(|| JsonError::missing_field("level"))?
+                .to_string()
+                .parse::<GelfLevel>()?,
+            data.get("timestamp")
+                .ok_or_else(|| JsonError::missing_field("timestamp"))?
+                .as_f64()
+                .ok_or_else(|| JsonError::invalid_type(Unexpected::Other("timestamp"), &"u8"))
+                .and_then(valid_timestamp)?,
+        ),
+        ParseMode::Lenient => (
+            coerce_level(data.get("level"), &mut coercions)?,
+            coerce_timestamp(data.get("timestamp"), &mut coercions)?,
+        ),
+    };
+    let version = data
+        .get("version")
+        .ok_or_else(|| JsonError::missing_field("version"))?
+        .as_str()
+        .ok_or_else(|| JsonError::invalid_type(Unexpected::Other("version"), &"string"))?
+        .to_string();
+    if mode == ParseMode::Strict && !SUPPORTED_VERSIONS.contains(&version.as_str()) {
+        return Err(JsonError::invalid_value(
+            Unexpected::Str(&version),
+            &"gelf version 1.0 or 1.1",
+        ));
+    }
+    let line = match (data.get("line"), mode) {
+        (None, _) | (Some(Value::Null), _) => None,
+        (Some(Value::String(line)), ParseMode::Lenient) => {
+            let parsed = line
+                .trim()
+                .parse()
+                .map_err(|_| JsonError::invalid_value(Unexpected::Str(line), &"line number"))?;
+            coercions.push(format!("line: string {:?}", line));
+            Some(parsed)
+        }
+        (Some(line), _) => Some(
+            line.as_u64()
+                .ok_or_else(|| JsonError::invalid_type(Unexpected::Other("line"), &"u64"))?,
+        ),
+    };
+
     Ok(GelfData {
         host: data
             .get("host")
@@ -99,33 +261,103 @@ fn to_gelf(data: Map<String, Value>) -> JsonResult<GelfData> {
             .as_str()
             .ok_or_else(|| JsonError::invalid_type(Unexpected::Other("host"), &"string"))?
             .to_string(),
-        level: data
-            .get("level")
-            .ok_or_else(|| JsonError::missing_field("level"))?
-            .to_string()
-            .parse::<GelfLevel>()?,
+        level,
         short_message: data
             .get("short_message")
             .ok_or_else(|| JsonError::missing_field("short_message"))?
             .as_str()
             .ok_or_else(|| JsonError::invalid_type(Unexpected::Other("short_message"), &"string"))?
             .to_string(),
-        timestamp: data
-            .get("timestamp")
-            .ok_or_else(|| JsonError::missing_field("timestamp"))?
-            .as_f64()
-            .ok_or_else(|| JsonError::invalid_type(Unexpected::Other("timestamp"), &"u8"))?,
-        version: data
-            .get("version")
-            .ok_or_else(|| JsonError::missing_field("version"))?
-            .as_str()
-            .ok_or_else(|| JsonError::invalid_type(Unexpected::Other("version"), &"string"))?
-            .to_string(),
+        full_message: optional_str(&data, "full_message", mode, &mut coercions)?,
+        timestamp,
+        version,
+        facility: optional_str(&data, "facility", mode, &mut coercions)?,
+        file: optional_str(&data, "file", mode, &mut coercions)?,
+        line,
         meta,
         mechanism_data,
+        coercions,
     })
 }
 
+/// Optional string field, the lenient mode stringifies numbers and booleans,
+/// e.g. numeric syslog facility
+fn optional_str(
+    data: &Map<String, Value>,
+    field: &'static str,
+    mode: ParseMode,
+    coercions: &mut Vec<String>,
+) -> JsonResult<Option<String>> {
+    match (data.get(field), mode) {
+        (None, _) | (Some(Value::Null), _) => Ok(None),
+        (Some(Value::String(value)), _) => Ok(Some(value.clone())),
+        (Some(value @ Value::Number(_)), ParseMode::Lenient) => {
+            coercions.push(format!("{}: number {}", field, value));
+            Ok(Some(value.to_string()))
+        }
+        (Some(value @ Value::Bool(_)), ParseMode::Lenient) => {
+            coercions.push(format!("{}: bool {}", field, value));
+            Ok(Some(value.to_string()))
+        }
+        (Some(_), _) => Err(JsonError::invalid_type(Unexpected::Other(field), &"string")),
+    }
+}
+
+/// Level of the lenient mode, `1` is used by default as the gelf spec says
+fn coerce_level(level: Option<&Value>, coercions: &mut Vec<String>) -> JsonResult<GelfLevel> {
+    match level {
+        None | Some(Value::Null) => {
+            coercions.push("level: default 1".to_string());
+            Ok(GelfLevel::Alert)
+        }
+        Some(Value::String(level)) => {
+            let parsed = level
+                .trim()
+                .parse()
+                .or_else(|_| GelfLevel::from_name(level))?;
+            coercions.push(format!("level: string {:?}", level));
+            Ok(parsed)
+        }
+        Some(level) => level.to_string().parse(),
+    }
+}
+
+/// Timestamp of the lenient mode, current time is used by default
+fn coerce_timestamp(timestamp: Option<&Value>, coercions: &mut Vec<String>) -> JsonResult<f64> {
+    match timestamp {
+        None | Some(Value::Null) => {
+            coercions.push("timestamp: default now".to_string());
+            Ok(SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0.0, |now| now.as_secs_f64()))
+        }
+        Some(Value::String(timestamp)) => {
+            let parsed = timestamp.trim().parse::<f64>().map_err(|_| {
+                JsonError::invalid_value(Unexpected::Str(timestamp), &"seconds since unix epoch")
+            })?;
+            let parsed = valid_timestamp(parsed)?;
+            coercions.push(format!("timestamp: string {:?}", timestamp));
+            Ok(parsed)
+        }
+        Some(timestamp) => timestamp
+            .as_f64()
+            .ok_or_else(|| JsonError::invalid_type(Unexpected::Other("timestamp"), &"number"))
+            .and_then(valid_timestamp),
+    }
+}
+
+/// Timestamp must be a finite time after the unix epoch, e.g. "nan" or "-1e300" are rejected
+fn valid_timestamp(timestamp: f64) -> JsonResult<f64> {
+    if timestamp.is_finite() && timestamp >= 0.0 {
+        Ok(timestamp)
+    } else {
+        Err(JsonError::invalid_value(
+            Unexpected::Float(timestamp),
+            &"seconds since unix epoch",
+        ))
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum GelfLevel {
     Emergency = 0,
@@ -159,6 +391,26 @@ impl FromStr for GelfLevel {
     }
 }
 
+impl GelfLevel {
+    /// Parse syslog level name, e.g. `error` or `warn`
+    fn from_name(name: &str) -> JsonResult<GelfLevel> {
+        match name.trim().to_lowercase().as_str() {
+            "emerg" | "emergency" | "panic" | "fatal" => Ok(GelfLevel::Emergency),
+            "alert" => Ok(GelfLevel::Alert),
+            "crit" | "critical" => Ok(GelfLevel::Critical),
+            "err" | "error" => Ok(GelfLevel::Error),
+            "warn" | "warning" => Ok(GelfLevel::Warning),
+            "notice" => Ok(GelfLevel::Notice),
+            "info" | "informational" => Ok(GelfLevel::Informational),
+            "debug" | "trace" => Ok(GelfLevel::Debug),
+            _ => Err(JsonError::invalid_value(
+                Unexpected::Str(name),
+                &"integers from 0 to 7 or syslog level names",
+            )),
+        }
+    }
+}
+
 #[cfg(test)]
 mod reader {
     use super::*;
@@ -166,16 +418,19 @@ mod reader {
 
     #[test]
     fn test_gelf() {
-        let r = to_gelf({
-            let mut temp = Map::new();
-            temp.insert("version".to_string(), json!("1.1"));
-            temp.insert("host".to_string(), json!("example.org"));
-            temp.insert("short_message".to_string(), json!("A short message"));
-            temp.insert("_some_info".to_string(), json!("foo"));
-            temp.insert("level".to_string(), json!(5));
-            temp.insert("timestamp".to_string(), json!(1_582_213_226));
-            temp
-        })
+        let r = to_gelf(
+            {
+                let mut temp = Map::new();
+                temp.insert("version".to_string(), json!("1.1"));
+                temp.insert("host".to_string(), json!("example.org"));
+                temp.insert("short_message".to_string(), json!("A short message"));
+                temp.insert("_some_info".to_string(), json!("foo"));
+                temp.insert("level".to_string(), json!(5));
+                temp.insert("timestamp".to_string(), json!(1_582_213_226));
+                temp
+            },
+            ParseMode::Strict,
+        )
         .unwrap();
 
         assert_eq!(r.version, "1.1");
@@ -185,9 +440,151 @@ mod reader {
         assert!(matches!(r.level, GelfLevel::Notice))
     }
 
+    #[test]
+    fn test_lenient() {
+        let message = br#"{
+            "version":"1.1",
+            "host":"example.org",
+            "short_message":"A short message",
+            "level":"error",
+            "timestamp":"1582213226.5"
+        }"#;
+        assert!(GelfDataWrapper::from_slice(message, ParseMode::Strict).is_err());
+
+        let r = GelfDataWrapper::from_slice(message, ParseMode::Lenient)
+            .unwrap()
+            .into_gelf();
+        assert!(matches!(r.level, GelfLevel::Error));
+        assert_eq!(r.timestamp, 1_582_213_226.5);
+        assert_eq!(
+            r.coercions,
+            vec![
+                r#"level: string "error""#.to_string(),
+                r#"timestamp: string "1582213226.5""#.to_string(),
+            ]
+        );
+
+        let r = GelfDataWrapper::from_slice(
+            br#"{"version":"1.1","host":"example.org","short_message":"A short message"}"#,
+            ParseMode::Lenient,
+        )
+        .unwrap()
+        .into_gelf();
+        assert!(matches!(r.level, GelfLevel::Alert));
+        assert!(r.timestamp > 0.0);
+        assert_eq!(r.coercions.len(), 2);
+
+        let r = GelfDataWrapper::from_slice(
+            br#"{"version":"1.1","host":"example.org","short_message":"A short message","level":"3","timestamp":1}"#,
+            ParseMode::Lenient,
+        )
+        .unwrap()
+        .into_gelf();
+        assert!(matches!(r.level, GelfLevel::Error));
+        assert!(GelfDataWrapper::from_slice(
+            br#"{"version":"1.1","host":"example.org","short_message":"A short message","level":"loud"}"#,
+            ParseMode::Lenient,
+        )
+        .is_err());
+
+        // empty and multibyte keys are not split by bytes
+        let r = GelfDataWrapper::from_slice(
+            r#"{"version":"1.1","host":"example.org","short_message":"A short message","":1,"ключ":2,"_ключ":3}"#.as_bytes(),
+            ParseMode::Lenient,
+        )
+        .unwrap()
+        .into_gelf();
+        assert_eq!(r.mechanism_data[""], 1);
+        assert_eq!(r.mechanism_data["ключ"], 2);
+        assert_eq!(r.meta["ключ"], 3);
+
+        for timestamp in &[r#""nan""#, r#""inf""#, r#""-1e300""#, "-1"] {
+            let message = format!(
+                r#"{{"version":"1.1","host":"example.org","short_message":"A short message","level":3,"timestamp":{}}}"#,
+                timestamp
+            );
+            assert!(
+                GelfDataWrapper::from_slice(message.as_bytes(), ParseMode::Lenient).is_err(),
+                "{}",
+                timestamp
+            );
+        }
+    }
+
+    #[test]
+    fn test_gelf_1_0() {
+        let message = |version: &str, line: &str| {
+            format!(
+                r#"{{"version":"{}","host":"example.org","short_message":"A short message",
+                "level":3,"timestamp":1582213226,"facility":"billing","file":"app.rb","line":{}}}"#,
+                version, line
+            )
+        };
+
+        let r = GelfDataWrapper::from_slice(message("1.0", "42").as_bytes(), ParseMode::Strict)
+            .unwrap();
+        assert_eq!(r.field("facility").unwrap(), "billing");
+        assert_eq!(r.field("line").unwrap(), "42");
+        let r = r.into_gelf();
+        assert_eq!(r.file.as_deref(), Some("app.rb"));
+        assert!(r.mechanism_data.is_empty());
+
+        assert!(
+            GelfDataWrapper::from_slice(message("2.0", "42").as_bytes(), ParseMode::Strict)
+                .is_err()
+        );
+        assert!(GelfDataWrapper::from_slice(
+            message("1.1", r#""42""#).as_bytes(),
+            ParseMode::Strict
+        )
+        .is_err());
+        let r =
+            GelfDataWrapper::from_slice(message("1.1", r#""42""#).as_bytes(), ParseMode::Lenient)
+                .unwrap()
+                .into_gelf();
+        assert_eq!(r.line, Some(42));
+
+        let message = br#"{"version":"1.0","host":"example.org","short_message":"A short message",
+            "level":3,"timestamp":1582213226,"facility":4,"file":"app.rb","full_message":true}"#;
+        assert!(GelfDataWrapper::from_slice(message, ParseMode::Strict).is_err());
+        let r = GelfDataWrapper::from_slice(message, ParseMode::Lenient)
+            .unwrap()
+            .into_gelf();
+        assert_eq!(r.facility.as_deref(), Some("4"));
+        assert_eq!(r.full_message.as_deref(), Some("true"));
+        assert_eq!(
+            r.coercions,
+            vec!["full_message: bool true", "facility: number 4"]
+        );
+    }
+
+    #[test]
+    fn test_field() {
+        let r = GelfDataWrapper::from_slice(
+            br#"{
+                "version":"1.1",
+                "host":"example.org",
+                "short_message":"A short message",
+                "level":5,
+                "facility":"billing",
+                "_service":"api",
+                "_port":8080,
+                "timestamp":1582213226
+            }"#,
+            ParseMode::Strict,
+        )
+        .unwrap();
+
+        assert_eq!(r.field("host").unwrap(), "example.org");
+        assert_eq!(r.field("facility").unwrap(), "billing");
+        assert_eq!(r.field("_service").unwrap(), "api");
+        assert_eq!(r.field("_port").unwrap(), "8080");
+        assert!(r.field("_missing").is_none());
+    }
+
     #[actix_rt::test]
     async fn test_actor() {
-        let gelf_reader = GelfReaderActor::new(1);
+        let gelf_reader = GelfReaderActor::new(1, ParseMode::Strict);
 
         let r = gelf_reader
             .send(GelfMessage(
@@ -0,0 +1,312 @@
+use crate::gelf::error::GelfError;
+use crate::gelf::gelf_message_processor::GelfProcessorMessage;
+use crate::gelf::gelf_reader::{GelfMessage, GelfReaderActor};
+use crate::gelf::unpacking::{UnPackActor, UnpackError, UnpackMessage};
+use crate::health;
+use crate::metrics::{EVENTS_DROPPED, PACKETS_RECEIVED};
+use crate::shutdown::Shutdown;
+use actix::dev::ToEnvelope;
+use actix::prelude::*;
+use hyper::body::HttpBody;
+use hyper::header::{CONTENT_ENCODING, CONTENT_LENGTH};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{error, warn};
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::net::{lookup_host, ToSocketAddrs};
+
+/// Path, which accepts gelf messages over http
+pub const GELF_PATH: &str = "/gelf";
+
+pub async fn new_http_acceptor<T, A>(
+    bind_addr: T,
+    gelf_processor: Arc<Addr<A>>,
+    reader: Arc<Addr<GelfReaderActor>>,
+    unpacker: Arc<Addr<UnPackActor>>,
+    max_body_size: usize,
+    shutdown: Shutdown,
+) where
+    T: ToSocketAddrs,
+    A: Actor + Handler<GelfProcessorMessage> + Send,
+    A::Context: ToEnvelope<A, GelfProcessorMessage>,
+{
+    let addr = lookup_host(bind_addr)
+        .await
+        .unwrap()
+        .next()
+        .unwrap_or_else(|| panic!("http address is not resolved"));
+
+    let make_service = make_service_fn(move |_| {
+        let gelf_processor = Arc::clone(&gelf_processor);
+        let reader = Arc::clone(&reader);
+        let unpacker = Arc::clone(&unpacker);
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                handle_request(
+                    req,
+                    Arc::clone(&gelf_processor),
+                    Arc::clone(&reader),
+                    Arc::clone(&unpacker),
+                    max_body_size,
+                )
+            }))
+        }
+    });
+
+    let server = Server::bind(&addr)
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown.wait());
+    health::listener_bound("http");
+    if let Err(e) = server.await {
+        error!("{}", GelfError::from_err("http server error", e));
+    }
+}
+
+async fn handle_request<A>(
+    req: Request<Body>,
+    gelf_processor: Arc<Addr<A>>,
+    reader: Arc<Addr<GelfReaderActor>>,
+    unpacker: Arc<Addr<UnPackActor>>,
+    max_body_size: usize,
+) -> Result<Response<Body>, Infallible>
+where
+    A: Actor + Handler<GelfProcessorMessage> + Send,
+    A::Context: ToEnvelope<A, GelfProcessorMessage>,
+{
+    let status = match (req.method(), req.uri().path()) {
+        (&Method::POST, GELF_PATH) => {
+            PACKETS_RECEIVED.with_label_values(&["http"]).inc();
+            match accept_gelf(req, gelf_processor, reader, unpacker, max_body_size).await {
+                Ok(()) => StatusCode::ACCEPTED,
+                Err((status, e)) => {
+                    warn!("{}", e);
+                    status
+                }
+            }
+        }
+        (_, GELF_PATH) => StatusCode::METHOD_NOT_ALLOWED,
+        _ => StatusCode::NOT_FOUND,
+    };
+
+    Ok(Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap())
+}
+
+async fn accept_gelf<A>(
+    req: Request<Body>,
+    gelf_processor: Arc<Addr<A>>,
+    reader: Arc<Addr<GelfReaderActor>>,
+    unpacker: Arc<Addr<UnPackActor>>,
+    max_body_size: usize,
+) -> Result<(), (StatusCode, GelfError)>
+where
+    A: Actor + Handler<GelfProcessorMessage> + Send,
+    A::Context: ToEnvelope<A, GelfProcessorMessage>,
+{
+    let is_packed = match req.headers().get(CONTENT_ENCODING).map(|h| h.to_str()) {
+        None => false,
+        Some(Ok("identity")) => false,
+        Some(Ok("gzip")) | Some(Ok("x-gzip")) | Some(Ok("deflate")) => true,
+        Some(_) => {
+            return Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                GelfError::new("http unsupported content encoding"),
+            ))
+        }
+    };
+
+    let buf = read_body(req, max_body_size).await?;
+
+    let buf = if is_packed {
+        unpacker
+            .send(UnpackMessage(buf, max_body_size))
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    GelfError::from_err("unpacker actor mailing error", e),
+                )
+            })?
+            .map_err(|e| match e {
+                UnpackError::TooLarge(_) => {
+                    EVENTS_DROPPED.with_label_values(&["http_too_large"]).inc();
+                    (
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        GelfError::new(&format!("http {}", e)),
+                    )
+                }
+                e => (
+                    StatusCode::BAD_REQUEST,
+                    GelfError::from_err("http unpacking data error", e),
+                ),
+            })?
+    } else {
+        buf
+    };
+
+    let gelf_processor_message = reader
+        .send(GelfMessage(buf))
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                GelfError::from_err("gelf actor mailing error", e),
+            )
+        })?
+        .map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                GelfError::from_err("http parsing gelf error", e),
+            )
+        })
+        .map(GelfProcessorMessage)?;
+
+    gelf_processor
+        .send(gelf_processor_message)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                GelfError::from_err("gelf actor processing error", e),
+            )
+        })?;
+
+    Ok(())
+}
+
+/// Read the request body, which is not larger than max size.
+/// Content length is checked before reading, the streaming body is checked while reading.
+async fn read_body(
+    req: Request<Body>,
+    max_size: usize,
+) -> Result<Vec<u8>, (StatusCode, GelfError)> {
+    let too_large = || {
+        EVENTS_DROPPED.with_label_values(&["http_too_large"]).inc();
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            GelfError::new(&format!("http body is larger than {} bytes", max_size)),
+        )
+    };
+
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > max_size as u64) {
+        return Err(too_large());
+    }
+
+    let mut body = req.into_body();
+    let mut buf = Vec::with_capacity(content_length.map_or(0, |length| length as usize));
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                GelfError::from_err("failed to read http body", e),
+            )
+        })?;
+        if buf.len() + chunk.len() > max_size {
+            return Err(too_large());
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod acceptor {
+    use super::*;
+    use crate::gelf::gelf_message_processor::GelfPrinterActor;
+    use crate::gelf::gelf_reader::ParseMode;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const MESSAGE: &[u8] = br#"{
+        "version":"1.1",
+        "host":"example.org",
+        "short_message":"A short message",
+        "level":5,
+        "_some_info":"foo",
+        "timestamp":1582213226
+    }"#;
+
+    async fn send(req: Request<Body>) -> StatusCode {
+        handle_request(
+            req,
+            Arc::new(GelfPrinterActor::new()),
+            Arc::new(GelfReaderActor::new(1, ParseMode::Strict)),
+            Arc::new(UnPackActor::new(1)),
+            MESSAGE.len(),
+        )
+        .await
+        .unwrap()
+        .status()
+    }
+
+    #[actix_rt::test]
+    async fn test_accepted() {
+        let req = Request::post(GELF_PATH).body(Body::from(MESSAGE)).unwrap();
+        assert_eq!(send(req).await, StatusCode::ACCEPTED);
+    }
+
+    #[actix_rt::test]
+    async fn test_gzip_accepted() {
+        let mut e = GzEncoder::new(Vec::new(), Compression::default());
+        e.write_all(MESSAGE).unwrap();
+
+        let req = Request::post(GELF_PATH)
+            .header(CONTENT_ENCODING, "gzip")
+            .body(Body::from(e.finish().unwrap()))
+            .unwrap();
+        assert_eq!(send(req).await, StatusCode::ACCEPTED);
+    }
+
+    #[actix_rt::test]
+    async fn test_bad_request() {
+        let req = Request::post(GELF_PATH)
+            .body(Body::from(r#"{"version":"1.1"}"#))
+            .unwrap();
+        assert_eq!(send(req).await, StatusCode::BAD_REQUEST);
+
+        let req = Request::get(GELF_PATH).body(Body::empty()).unwrap();
+        assert_eq!(send(req).await, StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[actix_rt::test]
+    async fn test_too_large() {
+        let large = [MESSAGE, b" "].concat();
+        let req = Request::post(GELF_PATH)
+            .header(CONTENT_LENGTH, large.len())
+            .body(Body::from(large.clone()))
+            .unwrap();
+        assert_eq!(send(req).await, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // the streaming body without content length is limited while reading
+        let chunks: Vec<Result<Vec<u8>, Infallible>> =
+            vec![Ok(MESSAGE.to_vec()), Ok(b" ".to_vec())];
+        let req = Request::post(GELF_PATH)
+            .body(Body::wrap_stream(futures::stream::iter(chunks)))
+            .unwrap();
+        assert_eq!(send(req).await, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_rt::test]
+    async fn test_gzip_bomb() {
+        let mut e = GzEncoder::new(Vec::new(), Compression::best());
+        e.write_all(&vec![b' '; 1024 * 1024]).unwrap();
+        let packed = e.finish().unwrap();
+        assert!(packed.len() < MESSAGE.len() * 10);
+
+        let req = Request::post(GELF_PATH)
+            .header(CONTENT_ENCODING, "gzip")
+            .body(Body::from(packed))
+            .unwrap();
+        assert_eq!(send(req).await, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -1,6 +1,10 @@
+pub mod chunking;
 pub mod error;
+pub mod framing;
 pub mod gelf_message_processor;
 pub mod gelf_reader;
+pub mod http_acceptor;
 pub mod tcp_acceptor;
+pub mod tls;
 pub mod udp_acceptor;
 pub mod unpacking;
@@ -1,24 +1,49 @@
 use crate::gelf::error::GelfError;
+use crate::gelf::framing::GelfFrameDecoder;
 use crate::gelf::gelf_message_processor::GelfProcessorMessage;
 use crate::gelf::gelf_reader::{GelfMessage, GelfReaderActor};
+use crate::gelf::tls::TcpTls;
+use crate::health;
+use crate::metrics::{PACKETS_RECEIVED, TLS_HANDSHAKE_FAILURES};
+use crate::shutdown::{until_shutdown, Shutdown};
 use actix::dev::ToEnvelope;
 use actix::prelude::*;
+use futures::channel::oneshot::{channel, Sender};
 use futures::prelude::*;
+use log::{error, warn};
+use std::io;
 use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
-use tokio::prelude::io::AsyncReadExt;
+use tokio::time::timeout;
+use tokio_rustls::server::TlsStream;
+use tokio_util::codec::FramedRead;
 
 pub async fn new_tcp_acceptor<T, A>(
     bind_addr: T,
     gelf_processor: Arc<Addr<A>>,
     reader: Arc<Addr<GelfReaderActor>>,
+    max_frame_size: usize,
+    split_on_newline: bool,
+    tls: Option<TcpTls>,
+    shutdown: Shutdown,
 ) where
     T: ToSocketAddrs,
     A: Actor + Handler<GelfProcessorMessage>,
     A::Context: ToEnvelope<A, GelfProcessorMessage>,
 {
     let listener = TcpListener::bind(bind_addr).await.unwrap();
-    TcpActor::new(listener, gelf_processor, reader);
+    health::listener_bound("tcp");
+    let (drained, on_drained) = channel();
+    TcpActor::new(
+        listener,
+        gelf_processor,
+        reader,
+        GelfFrameDecoder::new(max_frame_size, split_on_newline),
+        tls,
+        shutdown,
+        drained,
+    );
+    let _ = on_drained.await;
 }
 
 pub struct TcpActor<T>
@@ -28,6 +53,12 @@ where
 {
     reader: Arc<Addr<GelfReaderActor>>,
     gelf_processor: Arc<Addr<T>>,
+    decoder: GelfFrameDecoder,
+    tls: Option<TcpTls>,
+    shutdown: Shutdown,
+    connections: usize,
+    closed: bool,
+    drained: Option<Sender<()>>,
 }
 
 impl<T> TcpActor<T>
@@ -39,15 +70,32 @@ where
         listener: TcpListener,
         gelf_processor: Arc<Addr<T>>,
         reader: Arc<Addr<GelfReaderActor>>,
+        decoder: GelfFrameDecoder,
+        tls: Option<TcpTls>,
+        shutdown: Shutdown,
+        drained: Sender<()>,
     ) -> Addr<TcpActor<T>> {
         TcpActor::create(|ctx| {
-            ctx.add_stream(read_tcp(listener));
+            ctx.add_stream(until_shutdown(read_tcp(listener), shutdown.clone()));
             TcpActor {
                 reader,
                 gelf_processor,
+                decoder,
+                tls,
+                shutdown,
+                connections: 0,
+                closed: false,
+                drained: Some(drained),
             }
         })
     }
+
+    /// Stop actor, when new connections are not accepted anymore and all of them are closed
+    fn stop_if_drained(&mut self, ctx: &mut Context<Self>) {
+        if self.closed && self.connections == 0 {
+            ctx.stop();
+        }
+    }
 }
 
 impl<T> Actor for TcpActor<T>
@@ -56,6 +104,12 @@ where
     T::Context: ToEnvelope<T, GelfProcessorMessage>,
 {
     type Context = Context<Self>;
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if let Some(drained) = self.drained.take() {
+            let _ = drained.send(());
+        }
+    }
 }
 
 pub struct TcpPacket(TcpStream);
@@ -69,60 +123,108 @@ where
     T: Actor + Handler<GelfProcessorMessage>,
     T::Context: ToEnvelope<T, GelfProcessorMessage>,
 {
-    fn handle(&mut self, TcpPacket(mut socket): TcpPacket, ctx: &mut Context<Self>) {
+    fn handle(&mut self, TcpPacket(socket): TcpPacket, ctx: &mut Context<Self>) {
         let reader_actor = Arc::clone(&self.reader);
         let processor_actor = Arc::clone(&self.gelf_processor);
+        let tls = self.tls.clone();
+        let decoder = self.decoder.clone();
+        let shutdown = self.shutdown.clone();
+        self.connections += 1;
 
         ctx.spawn(
             async move {
-                loop {
-                    let mut buf = Vec::with_capacity(99 * 8192);
-                    let n = socket
-                        .read_to_end(&mut buf)
-                        .await
-                        .map_err(|e| GelfError::from_err("failed to read from socket", e))
-                        .and_then(|n| match n {
-                            0 => Err(GelfError::new("socket closed")),
-                            _ => Ok(n),
-                        });
-
-                    let gelf_message = match n {
-                        Ok(n) => {
-                            buf.truncate(n - 1);
-                            GelfMessage(buf)
-                        }
-                        Err(e) => {
-                            eprintln!("{}", e);
-                            return;
-                        }
-                    };
-
-                    let reader = reader_actor
-                        .send(gelf_message)
-                        .await
-                        .map_err(|e| GelfError::from_err("gelf actor mailing error", e))
-                        .and_then(|reader| {
-                            reader.map_err(|e| GelfError::from_err("tcp parsing gelf error", e))
-                        })
-                        .map(GelfProcessorMessage);
-
-                    let gelf_processor_message = match reader {
-                        Ok(r) => r,
-                        Err(e) => {
-                            eprintln!("{}", e);
-                            return;
+                match tls {
+                    Some(tls) => {
+                        if let Some(socket) = accept_tls(&tls, socket).await {
+                            let frames = FramedRead::new(socket, decoder);
+                            read_frames(frames, shutdown, reader_actor, processor_actor).await
                         }
-                    };
-
-                    if let Err(e) = processor_actor.send(gelf_processor_message).await {
-                        eprintln!("{}", GelfError::from_err("gelf actor processing error", e));
-                        return;
+                    }
+                    None => {
+                        let frames = FramedRead::new(socket, decoder);
+                        read_frames(frames, shutdown, reader_actor, processor_actor).await
                     }
                 }
             }
-            .into_actor(self),
+            .into_actor(self)
+            .map(|_, act, ctx| {
+                act.connections -= 1;
+                act.stop_if_drained(ctx);
+            }),
         );
     }
+
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        self.closed = true;
+        self.stop_if_drained(ctx);
+    }
+}
+
+/// Finish tls handshake of the connection, it is closed on error or when handshake takes too long
+async fn accept_tls(tls: &TcpTls, socket: TcpStream) -> Option<TlsStream<TcpStream>> {
+    match timeout(tls.handshake_timeout, tls.acceptor.accept(socket)).await {
+        Ok(Ok(socket)) => Some(socket),
+        Ok(Err(e)) => {
+            TLS_HANDSHAKE_FAILURES.with_label_values(&["error"]).inc();
+            warn!("{}", GelfError::from_err("tls handshake error", e));
+            None
+        }
+        Err(_) => {
+            TLS_HANDSHAKE_FAILURES.with_label_values(&["timeout"]).inc();
+            warn!(
+                "tls handshake is not finished in {:?}, connection is closed",
+                tls.handshake_timeout
+            );
+            None
+        }
+    }
+}
+
+/// Read frames of the connection until it is closed or shutdown is triggered
+async fn read_frames<S, A>(
+    frames: S,
+    shutdown: Shutdown,
+    reader_actor: Arc<Addr<GelfReaderActor>>,
+    processor_actor: Arc<Addr<A>>,
+) where
+    S: Stream<Item = io::Result<Vec<u8>>>,
+    A: Actor + Handler<GelfProcessorMessage>,
+    A::Context: ToEnvelope<A, GelfProcessorMessage>,
+{
+    let mut frames = Box::pin(until_shutdown(frames, shutdown));
+
+    while let Some(frame) = frames.next().await {
+        let gelf_message = match frame {
+            Ok(frame) => {
+                PACKETS_RECEIVED.with_label_values(&["tcp"]).inc();
+                GelfMessage(frame)
+            }
+            Err(e) => {
+                warn!("{}", GelfError::from_err("failed to read from socket", e));
+                return;
+            }
+        };
+
+        let reader = reader_actor
+            .send(gelf_message)
+            .await
+            .map_err(|e| GelfError::from_err("gelf actor mailing error", e))
+            .and_then(|reader| reader.map_err(|e| GelfError::from_err("tcp parsing gelf error", e)))
+            .map(GelfProcessorMessage);
+
+        let gelf_processor_message = match reader {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("{}", e);
+                continue;
+            }
+        };
+
+        if let Err(e) = processor_actor.send(gelf_processor_message).await {
+            error!("{}", GelfError::from_err("gelf actor processing error", e));
+            return;
+        }
+    }
 }
 
 fn read_tcp(listener: TcpListener) -> impl Stream<Item = TcpPacket> {
@@ -134,3 +236,33 @@ fn read_tcp(listener: TcpListener) -> impl Stream<Item = TcpPacket> {
     })
     .map(TcpPacket)
 }
+
+#[cfg(test)]
+mod handshake {
+    use crate::gelf::tcp_acceptor::accept_tls;
+    use crate::gelf::tls::TcpTls;
+    use crate::metrics::TLS_HANDSHAKE_FAILURES;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::net::TcpStream;
+    use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+    use tokio_rustls::TlsAcceptor;
+
+    #[actix_rt::test]
+    async fn test_handshake_timeout() {
+        let tls = TcpTls {
+            acceptor: TlsAcceptor::from(Arc::new(ServerConfig::new(NoClientAuth::new()))),
+            handshake_timeout: Duration::from_millis(50),
+        };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        // client connects, but never sends the client hello
+        let _client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (socket, _) = listener.accept().unwrap();
+        let socket = TcpStream::from_std(socket).unwrap();
+
+        let timeouts = TLS_HANDSHAKE_FAILURES.with_label_values(&["timeout"]);
+        let before = timeouts.get();
+        assert!(accept_tls(&tls, socket).await.is_none());
+        assert!(timeouts.get() > before);
+    }
+}
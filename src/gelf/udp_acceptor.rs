@@ -1,33 +1,143 @@
+use crate::gelf::chunking::{
+    is_chunk, ChunkError, ChunkPolicy, ChunkedMessage, MessageChunk, MessageId,
+};
 use crate::gelf::gelf_message_processor::GelfProcessorMessage;
 use crate::gelf::gelf_reader::{GelfMessage, GelfReaderActor};
-use crate::gelf::unpacking::{UnPackActor, UnpackMessage};
+use crate::gelf::unpacking::{UnPackActor, UnpackError, UnpackMessage};
+use crate::health;
+use crate::metrics::{CHUNKS_ASSEMBLED, CHUNKS_EXPIRED, EVENTS_DROPPED, PACKETS_RECEIVED};
+use crate::shutdown::{until_shutdown, Shutdown};
 use actix::dev::ToEnvelope;
 use actix::prelude::*;
+use futures::channel::oneshot::{channel, Sender};
 use futures::prelude::*;
-use std::net::SocketAddr;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket as StdUdpSocket};
 use tokio::net::udp::RecvHalf;
-use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::net::{lookup_host, ToSocketAddrs, UdpSocket};
 
 use crate::gelf::error::GelfError;
-use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashMap};
+use log::{debug, error, warn};
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::SystemTime;
+
+/// Maximum payload of the udp datagram
+pub const MAX_UDP_PAYLOAD: usize = 65507;
+
+/// Settings of the udp acceptor
+#[derive(Clone, Copy, Debug)]
+pub struct UdpSettings {
+    /// Maximum udp packets in processing
+    pub max_in_flight: usize,
+    /// Maximum size of the datagram, larger datagrams are dropped
+    pub recv_buffer_size: usize,
+    /// Count of the sockets, which are bound with SO_REUSEPORT and read on their own threads
+    pub sockets: usize,
+    /// SO_RCVBUF of the sockets, system default is used when it is not set
+    pub socket_recv_buffer: Option<usize>,
+    pub chunk_policy: ChunkPolicy,
+}
 
 pub async fn new_udp_acceptor<T, A>(
     bind_addr: T,
     gelf_processor: Arc<Addr<A>>,
     reader: Arc<Addr<GelfReaderActor>>,
     unpacker: Arc<Addr<UnPackActor>>,
-    max_parallel_chunks: usize,
+    settings: UdpSettings,
+    shutdown: Shutdown,
 ) where
     T: ToSocketAddrs,
     A: Actor + Handler<GelfProcessorMessage>,
     A::Context: ToEnvelope<A, GelfProcessorMessage>,
 {
-    let socket = UdpSocket::bind(bind_addr).await.unwrap();
-    let (recv, _) = socket.split();
-    UdpActor::new(recv, gelf_processor, reader, unpacker, max_parallel_chunks);
+    let addr = lookup_host(bind_addr)
+        .await
+        .unwrap()
+        .next()
+        .unwrap_or_else(|| panic!("udp address is not resolved"));
+
+    let mut receivers = Vec::with_capacity(settings.sockets);
+    for _ in 0..settings.sockets {
+        let socket = bind_udp(addr, &settings)
+            .unwrap_or_else(|e| panic!("failed to bind udp socket {}: {}", addr, e));
+        let arbiter = match settings.sockets {
+            1 => Arbiter::current(),
+            _ => Arbiter::new(),
+        };
+        let (drained, on_drained) = channel();
+        let gelf_processor = Arc::clone(&gelf_processor);
+        let reader = Arc::clone(&reader);
+        let unpacker = Arc::clone(&unpacker);
+        let shutdown = shutdown.clone();
+
+        // SO_REUSEPORT selects the socket by the sender address and port,
+        // so all chunks of the message are received by the same socket and its own chunk acceptor
+        UdpActor::start_in_arbiter(&arbiter, move |ctx| {
+            let unchanker = Arc::new(ChunkAcceptor::new(settings.chunk_policy));
+            let (recv, _) = UdpSocket::from_std(socket)
+                .unwrap_or_else(|e| panic!("failed to register udp socket: {}", e))
+                .split();
+            ctx.add_stream(until_shutdown(
+                read_udp(recv, settings.recv_buffer_size),
+                shutdown,
+            ));
+            UdpActor::new(
+                gelf_processor,
+                reader,
+                unpacker,
+                unchanker,
+                settings.max_in_flight,
+                settings.chunk_policy.max_message_bytes,
+                drained,
+            )
+        });
+        receivers.push(on_drained.map(move |_| {
+            if settings.sockets > 1 {
+                arbiter.stop();
+            }
+        }));
+    }
+    health::listener_bound("udp");
+    future::join_all(receivers).await;
+}
+
+/// Bind udp socket, sockets are bound with SO_REUSEPORT when there are several of them
+fn bind_udp(addr: SocketAddr, settings: &UdpSettings) -> io::Result<StdUdpSocket> {
+    let domain = match addr {
+        SocketAddr::V4(_) => Domain::ipv4(),
+        SocketAddr::V6(_) => Domain::ipv6(),
+    };
+    let socket = Socket::new(domain, Type::dgram(), Some(Protocol::udp()))?;
+    if settings.sockets > 1 {
+        set_reuse_port(&socket)?;
+    }
+    if let Some(size) = settings.socket_recv_buffer {
+        socket.set_recv_buffer_size(size)?;
+        let applied = socket.recv_buffer_size()?;
+        if applied < size {
+            warn!(
+                "udp SO_RCVBUF is limited by the system to {} bytes instead of {}",
+                applied, size
+            );
+        }
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    Ok(socket.into_udp_socket())
+}
+
+#[cfg(unix)]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_socket: &Socket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "several udp sockets are supported on unix only",
+    ))
 }
 
 pub struct UdpActor<T>
@@ -39,6 +149,13 @@ where
     unchanker: Arc<Addr<ChunkAcceptor>>,
     reader: Arc<Addr<GelfReaderActor>>,
     gelf_processor: Arc<Addr<T>>,
+    in_flight: usize,
+    max_in_flight: usize,
+    /// Maximum size of the unpacked message
+    max_message_bytes: usize,
+    dropped: u64,
+    closed: bool,
+    drained: Option<Sender<()>>,
 }
 impl<T> UdpActor<T>
 where
@@ -46,21 +163,33 @@ where
     T::Context: ToEnvelope<T, GelfProcessorMessage>,
 {
     pub fn new(
-        recv: RecvHalf,
         gelf_processor: Arc<Addr<T>>,
         reader: Arc<Addr<GelfReaderActor>>,
         unpacker: Arc<Addr<UnPackActor>>,
-        max_parallel_chunks: usize,
-    ) -> Addr<UdpActor<T>> {
-        UdpActor::create(|ctx| {
-            ctx.add_stream(read_udp(recv));
-            UdpActor {
-                unpacker,
-                reader,
-                gelf_processor,
-                unchanker: Arc::new(ChunkAcceptor::new(max_parallel_chunks)),
-            }
-        })
+        unchanker: Arc<Addr<ChunkAcceptor>>,
+        max_in_flight: usize,
+        max_message_bytes: usize,
+        drained: Sender<()>,
+    ) -> UdpActor<T> {
+        UdpActor {
+            unpacker,
+            reader,
+            gelf_processor,
+            unchanker,
+            in_flight: 0,
+            max_in_flight: max_in_flight.max(1),
+            max_message_bytes,
+            dropped: 0,
+            closed: false,
+            drained: Some(drained),
+        }
+    }
+
+    /// Stop actor, when packets are not received anymore and all of them are processed
+    fn stop_if_drained(&mut self, ctx: &mut Context<Self>) {
+        if self.closed && self.in_flight == 0 {
+            ctx.stop();
+        }
     }
 }
 impl<T> Actor for UdpActor<T>
@@ -69,6 +198,12 @@ where
     T::Context: ToEnvelope<T, GelfProcessorMessage>,
 {
     type Context = Context<Self>;
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if let Some(drained) = self.drained.take() {
+            let _ = drained.send(());
+        }
+    }
 }
 
 pub struct UdpPacket(Vec<u8>, SocketAddr);
@@ -81,25 +216,45 @@ where
     T: Actor + Handler<GelfProcessorMessage>,
     T::Context: ToEnvelope<T, GelfProcessorMessage>,
 {
-    fn handle(&mut self, UdpPacket(buf, _addr): UdpPacket, ctx: &mut Context<Self>) {
+    fn handle(&mut self, UdpPacket(buf, addr): UdpPacket, ctx: &mut Context<Self>) {
+        PACKETS_RECEIVED.with_label_values(&["udp"]).inc();
+        if self.in_flight >= self.max_in_flight {
+            EVENTS_DROPPED.with_label_values(&["udp_in_flight"]).inc();
+            self.dropped += 1;
+            warn!(
+                "too many udp packets in processing, packet is dropped ({} dropped in total)",
+                self.dropped
+            );
+            return;
+        }
+        self.in_flight += 1;
+
         let reader_actor = Arc::clone(&self.reader);
         let processor_actor = Arc::clone(&self.gelf_processor);
         let unpacker_actor = Arc::clone(&self.unpacker);
-        let unchanker_actor = Arc::clone(&self.unchanker);
+        let max_message_bytes = self.max_message_bytes;
+        // Only chunks are sent to the chunk acceptor, whole messages go straight to the unpacker
+        let unchanker_actor = match is_chunk(&buf) {
+            true => Some(Arc::clone(&self.unchanker)),
+            false => None,
+        };
 
         ctx.spawn(
             async move {
-                let packed_buf_message = unchanker_actor
-                    .send(UnchankMessage(buf))
-                    .await
-                    .map_err(|e| GelfError::from_err("unchunker actor mailing error", e))
-                    .map_err(|e| {
-                        eprintln!("{}", e);
-                        e
-                    })
-                    .ok()
-                    .and_then(|p| p)
-                    .map(UnpackMessage);
+                let packed_buf_message = match unchanker_actor {
+                    Some(unchanker_actor) => unchanker_actor
+                        .send(UnchankMessage(buf, addr))
+                        .await
+                        .map_err(|e| GelfError::from_err("unchunker actor mailing error", e))
+                        .map_err(|e| {
+                            warn!("{}", e);
+                            e
+                        })
+                        .ok()
+                        .and_then(|p| p),
+                    None => Some(buf),
+                }
+                .map(|buf| UnpackMessage(buf, max_message_bytes));
 
                 let packed_buf_message = match packed_buf_message {
                     Some(p) => p,
@@ -112,13 +267,20 @@ where
                     .send(packed_buf_message)
                     .await
                     .map_err(|e| GelfError::from_err("unpacker actor mailing error", e))
-                    .and_then(|bm| bm.map_err(|e| GelfError::from_err("udp parsing data error", e)))
+                    .and_then(|bm| {
+                        bm.map_err(|e| {
+                            if let UnpackError::TooLarge(_) = e {
+                                EVENTS_DROPPED.with_label_values(&["udp_too_large"]).inc();
+                            }
+                            GelfError::from_err("udp parsing data error", e)
+                        })
+                    })
                     .map(GelfMessage);
 
                 let gelf_msg = match gelf_msg {
                     Ok(g) => g,
                     Err(e) => {
-                        eprintln!("{}", e);
+                        warn!("{}", e);
                         return;
                     }
                 };
@@ -131,7 +293,7 @@ where
                     .map_err(|e| GelfError::from_err("gelf actor mailing error", e))
                     .and_then(|reader| {
                         reader.map_err(|e| {
-                            println!(
+                            debug!(
                                 "Original response: {}",
                                 String::from_utf8_lossy(&original_message)
                             );
@@ -143,56 +305,135 @@ where
                 let gelf_msg = match gelf_msg {
                     Ok(g) => g,
                     Err(e) => {
-                        eprintln!("{}", e);
+                        warn!("{}", e);
                         return;
                     }
                 };
 
                 if let Err(e) = processor_actor.send(gelf_msg).await {
-                    eprintln!("gelf actor processing error: {}", e);
-                    return;
+                    error!("gelf actor processing error: {}", e);
                 }
             }
-            .into_actor(self),
+            .into_actor(self)
+            .map(|_, act, ctx| {
+                act.in_flight -= 1;
+                act.stop_if_drained(ctx);
+            }),
         );
     }
+
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        self.closed = true;
+        self.stop_if_drained(ctx);
+    }
 }
 
-fn read_udp(recv: RecvHalf) -> impl Stream<Item = UdpPacket> {
-    stream::unfold(recv, |mut recv| async {
-        let mut buf = vec![0; 8196];
-        let (n, addr) = match recv.recv_from(&mut buf).await {
-            Ok((n, addr)) => (n, addr),
-            Err(e) => panic!("udp handling panic: {:?}", e),
-        };
-        buf.truncate(n);
-        Some(((buf, addr), recv))
+fn read_udp(recv: RecvHalf, buffer_size: usize) -> impl Stream<Item = UdpPacket> {
+    // Extra byte of the buffer detects datagrams, which are larger than buffer size
+    let buf = vec![0; buffer_size + 1];
+    stream::unfold((recv, buf), move |(mut recv, mut buf)| async move {
+        loop {
+            let (n, addr) = match recv.recv_from(&mut buf).await {
+                Ok((n, addr)) => (n, addr),
+                Err(e) => panic!("udp handling panic: {:?}", e),
+            };
+            match take_datagram(&buf, n, buffer_size) {
+                Some(datagram) => return Some((UdpPacket(datagram, addr), (recv, buf))),
+                None => warn!(
+                    "udp datagram from {} is larger than {} bytes and dropped",
+                    addr, buffer_size
+                ),
+            }
+        }
     })
-    .map(|(buf, addr)| UdpPacket(buf, addr))
 }
 
-pub struct UnchankMessage(pub Vec<u8>);
+/// Copy received datagram from the reused buffer, truncated datagrams are dropped
+fn take_datagram(buf: &[u8], len: usize, buffer_size: usize) -> Option<Vec<u8>> {
+    if len > buffer_size {
+        EVENTS_DROPPED.with_label_values(&["udp_truncated"]).inc();
+        return None;
+    }
+    Some(buf[..len].to_vec())
+}
+
+/// Udp packet and its sender, chunks are assembled per sender
+pub struct UnchankMessage(pub Vec<u8>, pub SocketAddr);
 
 impl Message for UnchankMessage {
     type Result = Option<Vec<u8>>;
 }
 
-struct ChunkAcceptor {
-    chunked_messages: HashMap<String, (SystemTime, BinaryHeap<MessageChunk>)>,
-    max_parallel_chunks: usize,
+/// Chunked messages are identified by the sender address and the message id
+type ChunkKey = (SocketAddr, MessageId);
+
+pub struct ChunkAcceptor {
+    chunked_messages: HashMap<ChunkKey, ChunkedMessage>,
+    policy: ChunkPolicy,
 }
 
 impl ChunkAcceptor {
-    fn new(max_parallel_chunks: usize) -> Addr<ChunkAcceptor> {
+    fn new(policy: ChunkPolicy) -> Addr<ChunkAcceptor> {
         ChunkAcceptor::create(|_| ChunkAcceptor {
-            chunked_messages: HashMap::with_capacity(max_parallel_chunks),
-            max_parallel_chunks,
+            chunked_messages: HashMap::with_capacity(policy.max_messages),
+            policy,
         })
     }
+
+    /// Drop messages, which are not completed in time
+    fn sweep(&mut self) {
+        let timeout = self.policy.timeout;
+        let before = self.chunked_messages.len();
+        self.chunked_messages
+            .retain(|_, message| message.started_at.elapsed() <= timeout);
+
+        let expired = before - self.chunked_messages.len();
+        if expired > 0 {
+            CHUNKS_EXPIRED.inc_by(expired as i64);
+            debug!("{} chunked messages are expired", expired);
+        }
+    }
+
+    /// Drop the oldest message of the host or of all hosts to make place for the new one
+    fn evict_oldest(&mut self, source: Option<IpAddr>) {
+        let oldest = self
+            .chunked_messages
+            .iter()
+            .filter(|((addr, _), _)| source.is_none_or(|source| addr.ip() == source))
+            .min_by_key(|(_, message)| message.started_at)
+            .map(|(key, _)| *key);
+
+        if let Some((addr, message_id)) = oldest {
+            self.chunked_messages.remove(&(addr, message_id));
+            EVENTS_DROPPED.with_label_values(&["chunk_eviction"]).inc();
+            debug!("chunked message {:x} from {} is evicted", message_id, addr);
+        }
+    }
+
+    fn source_messages(&self, source: IpAddr) -> usize {
+        self.chunked_messages
+            .keys()
+            .filter(|(addr, _)| addr.ip() == source)
+            .count()
+    }
 }
 
 impl Actor for ChunkAcceptor {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.policy.timeout / 2, |act, _| act.sweep());
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        if !self.chunked_messages.is_empty() {
+            CHUNKS_EXPIRED.inc_by(self.chunked_messages.len() as i64);
+            warn!(
+                "{} chunked messages are not completed before stop",
+                self.chunked_messages.len()
+            );
+        }
+    }
 }
 
 impl Handler<UnchankMessage> for ChunkAcceptor {
@@ -200,122 +441,107 @@ impl Handler<UnchankMessage> for ChunkAcceptor {
 
     fn handle(
         &mut self,
-        UnchankMessage(buf): UnchankMessage,
+        UnchankMessage(buf, addr): UnchankMessage,
         _ctx: &mut Self::Context,
     ) -> Self::Result {
-        if is_chunk(&buf) {
-            let chunk = MessageChunk::new(buf);
-
-            let message_id = chunk.message_id.clone();
-            let sequence_count = chunk.sequence_count;
-
-            let (_, chunks) = self
-                .chunked_messages
-                .entry(message_id.clone())
-                .or_insert_with(|| (SystemTime::now(), BinaryHeap::with_capacity(128)));
-
-            chunks.push(chunk);
-            let chunks_len = { chunks.len() as u8 };
-
-            if self.chunked_messages.len() == self.max_parallel_chunks {
-                let invalid_keys: Vec<String> = self
-                    .chunked_messages
-                    .iter()
-                    .filter_map(|(k, (t, _))| {
-                        if t.elapsed().ok()?.as_secs() > 5 {
-                            return Some(k.clone());
-                        }
-                        None
-                    })
-                    .collect();
-
-                let invalid_keys: Option<Vec<BinaryHeap<MessageChunk>>> = invalid_keys
-                    .iter()
-                    .map(|k| self.chunked_messages.remove(k))
-                    .map(|v| v.map(|(_, v)| v))
-                    .collect();
-
-                if let Some(invalid_keys) = invalid_keys {
-                    println!("keys successfull cleared: {:#?}", invalid_keys)
-                } else {
-                    eprintln!("Fail to clear chunks hashmap")
-                }
+        if !is_chunk(&buf) {
+            return Some(buf);
+        }
+        let chunk = match MessageChunk::parse(&buf) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                EVENTS_DROPPED.with_label_values(&["chunk_invalid"]).inc();
+                warn!("invalid gelf chunk from {}: {}", addr, e);
+                return None;
             }
+        };
 
-            if sequence_count == chunks_len {
-                let parsed_buf: Vec<u8> = self
-                    .chunked_messages
-                    .remove(&message_id)?
-                    .1
-                    .into_sorted_vec()
-                    .into_iter()
-                    .flat_map(|chunk| chunk.message_chunk.into_iter())
-                    .collect();
-
-                return Some(parsed_buf);
+        let message_id = chunk.message_id;
+        let key = (addr, message_id);
+        let sequence_count = chunk.sequence_count;
+        let max_message_bytes = self.policy.max_message_bytes;
+        if !self.chunked_messages.contains_key(&key) {
+            let max_per_source = self.policy.max_messages_per_source;
+            if max_per_source > 0 && self.source_messages(addr.ip()) >= max_per_source {
+                self.evict_oldest(Some(addr.ip()));
+            }
+            if self.chunked_messages.len() >= self.policy.max_messages {
+                self.evict_oldest(None);
             }
-
-            None
-        } else {
-            Some(buf)
         }
-    }
-}
-
-#[derive(Clone, Debug)]
-struct MessageChunk {
-    message_id: String,
-    sequence_number: u8,
-    sequence_count: u8,
-    message_chunk: Vec<u8>,
-}
-
-impl MessageChunk {
-    fn new(buf: Vec<u8>) -> MessageChunk {
-        MessageChunk {
-            message_id: std::string::String::from_utf8_lossy(&buf[2..9]).into_owned(),
-            sequence_number: buf[10],
-            sequence_count: buf[11],
-            message_chunk: buf[12..].to_vec(),
+        let completed = self
+            .chunked_messages
+            .entry(key)
+            .or_insert_with(|| ChunkedMessage::new(sequence_count, max_message_bytes))
+            .add(chunk);
+
+        match completed {
+            Ok(true) => {
+                CHUNKS_ASSEMBLED.inc();
+                Some(self.chunked_messages.remove(&key)?.assemble())
+            }
+            Ok(false) => None,
+            Err(e @ ChunkError::Duplicate(_)) => {
+                debug!(
+                    "gelf chunk of the message {:x} from {} is ignored: {}",
+                    message_id, addr, e
+                );
+                None
+            }
+            Err(e @ ChunkError::TooLarge(_)) => {
+                self.chunked_messages.remove(&key);
+                EVENTS_DROPPED.with_label_values(&["chunk_too_large"]).inc();
+                warn!(
+                    "chunked message {:x} from {} is dropped: {}",
+                    message_id, addr, e
+                );
+                None
+            }
+            Err(e) => {
+                EVENTS_DROPPED.with_label_values(&["chunk_invalid"]).inc();
+                warn!(
+                    "gelf chunk of the message {:x} from {} is ignored: {}",
+                    message_id, addr, e
+                );
+                None
+            }
         }
     }
 }
 
-impl PartialEq for MessageChunk {
-    fn eq(&self, other: &Self) -> bool {
-        self.sequence_number.eq(&other.sequence_number)
-    }
-}
-impl PartialOrd for MessageChunk {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.sequence_number.partial_cmp(&other.sequence_number)
+#[cfg(test)]
+mod acceptor {
+    use crate::gelf::chunking::ChunkPolicy;
+    use crate::gelf::udp_acceptor::{
+        bind_udp, take_datagram, ChunkAcceptor, UdpSettings, UnchankMessage,
+    };
+    use crate::metrics::EVENTS_DROPPED;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::time::delay_for;
+
+    fn policy(max_messages: usize) -> ChunkPolicy {
+        ChunkPolicy {
+            timeout: Duration::from_millis(50),
+            max_messages,
+            max_messages_per_source: 0,
+            max_message_bytes: 1024,
+        }
     }
-}
 
-impl Eq for MessageChunk {}
-impl Ord for MessageChunk {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.sequence_number.cmp(&other.sequence_number)
+    fn from(host: u8) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, host], 12201))
     }
-}
 
-fn is_chunk(buf: &[u8]) -> bool {
-    if buf.len() <= 2 {
-        return false;
+    fn chunk(id: u8, number: u8) -> Vec<u8> {
+        let mut temp = vec![30, 15, 0, 0, 0, 0, 0, 0, 0, id, number, 2];
+        temp.append(&mut b"test".to_vec());
+        temp
     }
-    if !(buf[0] == 30 && buf[1] == 15) {
-        return false;
-    }
-    true
-}
-
-#[cfg(test)]
-mod acceptor {
-    use crate::gelf::udp_acceptor::{ChunkAcceptor, UnchankMessage};
 
     #[actix_rt::test]
     async fn test_unpacker() {
-        let unpacker_actor = ChunkAcceptor::new(5);
+        let unpacker_actor = ChunkAcceptor::new(policy(5));
 
         let message_1 = {
             let mut temp = vec![30, 15];
@@ -332,14 +558,126 @@ mod acceptor {
             temp
         };
         let response_1 = unpacker_actor
-            .send(UnchankMessage(message_1))
+            .send(UnchankMessage(message_1, from(1)))
             .await
             .unwrap();
         assert_eq!(response_1, None);
         let response_2 = unpacker_actor
-            .send(UnchankMessage(message_2))
+            .send(UnchankMessage(message_2, from(1)))
             .await
             .unwrap();
         assert_eq!(response_2, Some(b"testtest".to_vec()))
     }
+
+    #[actix_rt::test]
+    async fn test_malformed_chunks() {
+        let unpacker_actor = ChunkAcceptor::new(policy(5));
+        let invalid = EVENTS_DROPPED.with_label_values(&["chunk_invalid"]);
+        let dropped = invalid.get();
+
+        let short = vec![30, 15, 1, 2];
+        let response = unpacker_actor
+            .send(UnchankMessage(short, from(1)))
+            .await
+            .unwrap();
+        assert_eq!(response, None);
+        assert!(invalid.get() > dropped);
+
+        let chunk = |number: u8| {
+            let mut temp = vec![30, 15, 1, 2, 3, 4, 5, 6, 7, 8, number, 2];
+            temp.append(&mut b"test".to_vec());
+            temp
+        };
+        let chunks = vec![chunk(0), chunk(0), chunk(1), chunk(1)];
+        let mut received = Vec::new();
+        for message in chunks {
+            received.push(
+                unpacker_actor
+                    .send(UnchankMessage(message, from(1)))
+                    .await
+                    .unwrap(),
+            );
+        }
+        assert_eq!(received, vec![None, None, Some(b"testtest".to_vec()), None]);
+    }
+
+    #[actix_rt::test]
+    async fn test_expiry_and_eviction() {
+        let unpacker_actor = ChunkAcceptor::new(policy(1));
+        let mut received = Vec::new();
+
+        received.push(
+            unpacker_actor
+                .send(UnchankMessage(chunk(1, 0), from(1)))
+                .await
+                .unwrap(),
+        );
+        delay_for(Duration::from_millis(100)).await;
+        received.push(
+            unpacker_actor
+                .send(UnchankMessage(chunk(1, 1), from(1)))
+                .await
+                .unwrap(),
+        );
+        assert_eq!(received, vec![None, None]);
+
+        received.clear();
+        for message in [chunk(2, 0), chunk(3, 0), chunk(2, 1), chunk(3, 1)] {
+            received.push(
+                unpacker_actor
+                    .send(UnchankMessage(message, from(1)))
+                    .await
+                    .unwrap(),
+            );
+        }
+        assert_eq!(received, vec![None, None, None, None]);
+    }
+
+    #[actix_rt::test]
+    async fn test_source_isolation() {
+        let unpacker_actor = ChunkAcceptor::new(ChunkPolicy {
+            max_messages_per_source: 1,
+            ..policy(5)
+        });
+        let mut received = Vec::new();
+
+        for (message, host) in [
+            (chunk(2, 0), 2),
+            (chunk(2, 0), 1),
+            (chunk(3, 0), 1),
+            (chunk(2, 1), 1),
+            (chunk(2, 1), 2),
+        ] {
+            let response = unpacker_actor.send(UnchankMessage(message, from(host)));
+            received.push(response.await.unwrap());
+        }
+        assert_eq!(
+            received,
+            vec![None, None, None, None, Some(b"testtest".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_take_datagram() {
+        let buf = [1, 2, 3, 4, 5];
+        assert_eq!(take_datagram(&buf, 3, 4), Some(vec![1, 2, 3]));
+        assert_eq!(take_datagram(&buf, 4, 4), Some(vec![1, 2, 3, 4]));
+        assert_eq!(take_datagram(&buf, 5, 4), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_bind_reuse_port() {
+        let settings = UdpSettings {
+            max_in_flight: 1,
+            recv_buffer_size: 1024,
+            sockets: 2,
+            socket_recv_buffer: Some(64 * 1024),
+            chunk_policy: policy(1),
+        };
+        let first = bind_udp("127.0.0.1:0".parse().unwrap(), &settings).unwrap();
+        let addr = first.local_addr().unwrap();
+        let second = bind_udp(addr, &settings).unwrap();
+        assert_eq!(second.local_addr().unwrap(), addr);
+    }
 }
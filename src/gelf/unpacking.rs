@@ -1,13 +1,39 @@
+use crate::health::Ping;
+use crate::metrics::DECOMPRESSION_FAILURES;
 use actix::prelude::*;
 use flate2::read::{GzDecoder, ZlibDecoder};
-use std::io::Read;
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io::{self, Read};
 
-pub struct UnpackMessage(pub Vec<u8>);
+/// Packed message and the maximum size of the unpacked one
+pub struct UnpackMessage(pub Vec<u8>, pub usize);
 
 impl Message for UnpackMessage {
-    type Result = std::io::Result<Vec<u8>>;
+    type Result = Result<Vec<u8>, UnpackError>;
 }
 
+/// Error, which returned when message could not be unpacked
+#[derive(Debug)]
+pub enum UnpackError {
+    Io(io::Error),
+    /// Unpacked message is larger than the maximum size, e.g. it is a compression bomb
+    TooLarge(usize),
+}
+
+impl Display for UnpackError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            UnpackError::Io(e) => write!(f, "{}", e),
+            UnpackError::TooLarge(max_size) => {
+                write!(f, "unpacked message is larger than {} bytes", max_size)
+            }
+        }
+    }
+}
+
+impl Error for UnpackError {}
+
 pub struct UnPackActor;
 
 impl UnPackActor {
@@ -21,30 +47,48 @@ impl Actor for UnPackActor {
 }
 
 impl Handler<UnpackMessage> for UnPackActor {
-    type Result = std::io::Result<Vec<u8>>;
+    type Result = Result<Vec<u8>, UnpackError>;
 
     fn handle(
         &mut self,
-        UnpackMessage(msg): UnpackMessage,
+        UnpackMessage(msg, max_size): UnpackMessage,
         _ctx: &mut Self::Context,
     ) -> Self::Result {
-        let mut buf = msg.as_slice();
-        let mut parsed_buf = Vec::with_capacity(buf.len());
-
-        if is_zlib(buf) {
-            let mut zlib_decompressor = ZlibDecoder::new(buf);
-            let n = zlib_decompressor.read_to_end(&mut parsed_buf)?;
-            parsed_buf.truncate(n)
-        } else if is_gz(buf) {
-            let mut gzip_decompressor = GzDecoder::new(buf);
-            let n = gzip_decompressor.read_to_end(&mut parsed_buf)?;
-            parsed_buf.truncate(n)
-        } else {
-            let n = buf.read_to_end(&mut parsed_buf)?;
-            parsed_buf.truncate(n)
+        let unpacked = unpack(msg.as_slice(), max_size)
+            .map_err(UnpackError::Io)
+            .inspect_err(|_| DECOMPRESSION_FAILURES.inc())?;
+        if unpacked.len() > max_size {
+            return Err(UnpackError::TooLarge(max_size));
         }
-        Ok(parsed_buf)
+        Ok(unpacked)
+    }
+}
+
+impl Handler<Ping> for UnPackActor {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Ping, _ctx: &mut Self::Context) {}
+}
+
+/// Unpack the message, one byte more than the maximum size is read at most,
+/// so the caller could detect too large message
+fn unpack(buf: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+    let mut parsed_buf = Vec::with_capacity(buf.len().min(max_size));
+    let limit = max_size as u64 + 1;
+
+    if is_zlib(buf) {
+        let zlib_decompressor = ZlibDecoder::new(buf);
+        let n = zlib_decompressor.take(limit).read_to_end(&mut parsed_buf)?;
+        parsed_buf.truncate(n)
+    } else if is_gz(buf) {
+        let gzip_decompressor = GzDecoder::new(buf);
+        let n = gzip_decompressor.take(limit).read_to_end(&mut parsed_buf)?;
+        parsed_buf.truncate(n)
+    } else {
+        let n = buf.take(limit).read_to_end(&mut parsed_buf)?;
+        parsed_buf.truncate(n)
     }
+    Ok(parsed_buf)
 }
 
 fn is_gz(buf: &[u8]) -> bool {
@@ -99,11 +143,28 @@ mod unpacker {
         e.write_all(b"Test").unwrap();
 
         let r = gelf_unpacker
-            .send(UnpackMessage(e.finish().unwrap()))
+            .send(UnpackMessage(e.finish().unwrap(), 4))
             .await
             .unwrap()
             .unwrap();
 
         assert_eq!(String::from_utf8(r).unwrap(), "Test");
     }
+
+    #[actix_rt::test]
+    async fn test_too_large() {
+        let gelf_unpacker = UnPackActor::new(1);
+
+        // 10 MB of zeros are packed to a few kilobytes
+        let mut e = ZlibEncoder::new(Vec::new(), Compression::best());
+        e.write_all(&vec![0; 10 * 1024 * 1024]).unwrap();
+        let packed = e.finish().unwrap();
+        assert!(packed.len() < 64 * 1024);
+
+        let r = gelf_unpacker
+            .send(UnpackMessage(packed, 1024))
+            .await
+            .unwrap();
+        assert!(matches!(r, Err(UnpackError::TooLarge(1024))));
+    }
 }
@@ -0,0 +1,239 @@
+use actix::dev::ToEnvelope;
+use actix::prelude::*;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::lookup_host;
+use tokio::time::interval;
+
+lazy_static! {
+    static ref STATE: Mutex<Health> = Mutex::new(Health::default());
+}
+
+/// Failed sentry delivery makes gtsa not ready during this time, unless a later one succeeds
+const SENTRY_FAILURE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Message, which is answered by every actor of the pipeline.
+/// Stalled mailbox is detected, when the answer is not received in time.
+pub struct Ping;
+
+impl Message for Ping {
+    type Result = ();
+}
+
+/// State of the gtsa, which is exposed by the readiness and liveness probes
+#[derive(Default)]
+struct Health {
+    listeners: HashMap<&'static str, bool>,
+    sentry_resolved: bool,
+    sentry_accepted_at: Option<Instant>,
+    sentry_failed_at: Option<Instant>,
+    shutting_down: bool,
+    actors: HashMap<&'static str, Instant>,
+    stall_timeout: Duration,
+}
+
+impl Health {
+    /// Ready, when all listeners are bound, the sentry is reachable
+    /// and the graceful shutdown is not started
+    fn readiness(&self, now: Instant) -> Result<(), Vec<String>> {
+        let mut problems: Vec<String> = self
+            .listeners
+            .iter()
+            .filter(|(_, bound)| !**bound)
+            .map(|(name, _)| format!("{} listener is not bound", name))
+            .collect();
+        if !self.sentry_resolved && self.sentry_accepted_at.is_none() {
+            problems.push("sentry dsn is not resolved".to_string());
+        }
+        if let Some(failed_at) = self.sentry_failed_at {
+            let recent = now.saturating_duration_since(failed_at) <= SENTRY_FAILURE_WINDOW;
+            let recovered = self
+                .sentry_accepted_at
+                .is_some_and(|accepted_at| accepted_at > failed_at);
+            if recent && !recovered {
+                problems.push("sentry delivery is failing".to_string());
+            }
+        }
+        if self.shutting_down {
+            problems.push("shutdown is started".to_string());
+        }
+        problems.sort();
+        to_result(problems)
+    }
+
+    /// Alive, when all actors answer to the pings
+    fn liveness(&self, now: Instant) -> Result<(), Vec<String>> {
+        let mut problems: Vec<String> = self
+            .actors
+            .iter()
+            .filter(|(_, pong)| now.saturating_duration_since(**pong) > self.stall_timeout)
+            .map(|(name, pong)| {
+                format!(
+                    "{} mailbox is stalled for {:?}",
+                    name,
+                    now.saturating_duration_since(*pong)
+                )
+            })
+            .collect();
+        problems.sort();
+        to_result(problems)
+    }
+}
+
+fn to_result(problems: Vec<String>) -> Result<(), Vec<String>> {
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+/// Listener is enabled, gtsa is not ready until it is bound
+pub fn listener_enabled(name: &'static str) {
+    STATE.lock().unwrap().listeners.entry(name).or_insert(false);
+}
+
+pub fn listener_bound(name: &'static str) {
+    STATE.lock().unwrap().listeners.insert(name, true);
+}
+
+/// Sentry accepted an event
+pub fn sentry_accepted() {
+    STATE.lock().unwrap().sentry_accepted_at = Some(Instant::now());
+}
+
+/// Event is not sent to the sentry after all retries
+pub fn sentry_failed() {
+    STATE.lock().unwrap().sentry_failed_at = Some(Instant::now());
+}
+
+/// Graceful shutdown is started, load balancers should stop sending new messages
+pub fn shutdown_started() {
+    STATE.lock().unwrap().shutting_down = true;
+}
+
+pub fn readiness() -> Result<(), Vec<String>> {
+    STATE.lock().unwrap().readiness(Instant::now())
+}
+
+pub fn liveness() -> Result<(), Vec<String>> {
+    STATE.lock().unwrap().liveness(Instant::now())
+}
+
+/// Resolve hosts of the sentry dsns, until all of them are resolved
+pub async fn resolve_sentry(addrs: Vec<String>) {
+    let mut retry = interval(Duration::from_secs(5));
+    loop {
+        retry.tick().await;
+        let mut resolved = true;
+        for addr in &addrs {
+            if let Err(e) = lookup_host(addr.as_str()).await {
+                warn!("sentry host {} is not resolved: {}", addr, e);
+                resolved = false;
+            }
+        }
+        if resolved {
+            info!("sentry hosts are resolved");
+            STATE.lock().unwrap().sentry_resolved = true;
+            return;
+        }
+    }
+}
+
+/// Ping the actor periodically. Actor is stalled, when it does not answer longer than timeout.
+pub async fn watch_actor<A>(name: &'static str, actor: Arc<Addr<A>>, stall_timeout: Duration)
+where
+    A: Actor + Handler<Ping>,
+    A::Context: ToEnvelope<A, Ping>,
+{
+    {
+        let mut state = STATE.lock().unwrap();
+        state.stall_timeout = stall_timeout;
+        state.actors.insert(name, Instant::now());
+    }
+    let mut ticks = interval(stall_timeout / 4);
+    loop {
+        ticks.tick().await;
+        if actor.send(Ping).await.is_err() {
+            warn!("{} actor is stopped", name);
+            return;
+        }
+        STATE.lock().unwrap().actors.insert(name, Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod probes {
+    use super::*;
+
+    #[test]
+    fn test_readiness() {
+        let now = Instant::now();
+        let mut health = Health::default();
+        health.listeners.insert("udp", true);
+        health.listeners.insert("tcp", false);
+        assert_eq!(
+            health.readiness(now),
+            Err(vec![
+                "sentry dsn is not resolved".to_string(),
+                "tcp listener is not bound".to_string(),
+            ])
+        );
+
+        health.listeners.insert("tcp", true);
+        health.sentry_accepted_at = Some(now);
+        assert_eq!(health.readiness(now), Ok(()));
+
+        health.shutting_down = true;
+        assert_eq!(
+            health.readiness(now),
+            Err(vec!["shutdown is started".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_sentry_failures() {
+        let now = Instant::now();
+        let mut health = Health {
+            sentry_resolved: true,
+            sentry_accepted_at: Some(now),
+            ..Health::default()
+        };
+
+        let failed_at = now + Duration::from_secs(1);
+        health.sentry_failed_at = Some(failed_at);
+        assert_eq!(
+            health.readiness(failed_at),
+            Err(vec!["sentry delivery is failing".to_string()])
+        );
+        // old failure is forgotten, later accepted event recovers readiness at once
+        assert_eq!(
+            health.readiness(failed_at + SENTRY_FAILURE_WINDOW * 2),
+            Ok(())
+        );
+        health.sentry_accepted_at = Some(failed_at + Duration::from_secs(1));
+        assert_eq!(health.readiness(failed_at + Duration::from_secs(1)), Ok(()));
+    }
+
+    #[test]
+    fn test_liveness() {
+        let now = Instant::now();
+        let mut health = Health {
+            stall_timeout: Duration::from_secs(30),
+            ..Health::default()
+        };
+        health.actors.insert("reader", now);
+        health
+            .actors
+            .insert("processor", now - Duration::from_secs(10));
+        assert_eq!(health.liveness(now), Ok(()));
+
+        let later = now + Duration::from_secs(25);
+        let problems = health.liveness(later).unwrap_err();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("processor mailbox is stalled"));
+    }
+}
@@ -0,0 +1,132 @@
+use chrono::{SecondsFormat, Utc};
+use env_logger::fmt::Formatter;
+use env_logger::Builder;
+use log::{LevelFilter, Record};
+use serde_json::json;
+use std::fmt::{Display, Formatter as FmtFormatter, Result as FmtResult};
+use std::io::{Result as IoResult, Write};
+use std::str::FromStr;
+
+/// Output format of the gtsa logs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// Json object per line with timestamp, level, target and message
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            format => Err(format!(
+                "log format must be text or json, but {} is passed",
+                format
+            )),
+        }
+    }
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut FmtFormatter<'_>) -> FmtResult {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Log filter in the env_logger syntax, e.g. `info` or `warn,gtsa::sentry=debug`
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogFilter(String);
+
+impl FromStr for LogFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let directives = s.split('/').next().unwrap_or_default();
+        for directive in directives.split(',').map(str::trim) {
+            if let Some((_, level)) = directive.split_once('=') {
+                if level.parse::<LevelFilter>().is_err() {
+                    return Err(format!("invalid log level in {}", directive));
+                }
+            }
+        }
+        Ok(LogFilter(s.to_string()))
+    }
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        LogFilter("info".to_string())
+    }
+}
+
+impl Display for LogFilter {
+    fn fmt(&self, f: &mut FmtFormatter<'_>) -> FmtResult {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Install global logger with passed filter and format
+pub fn init(filter: &LogFilter, format: LogFormat) {
+    let mut builder = Builder::new();
+    builder.parse_filters(&filter.0);
+    if format == LogFormat::Json {
+        builder.format(write_json);
+    }
+    builder.init();
+}
+
+fn write_json(buf: &mut Formatter, record: &Record) -> IoResult<()> {
+    writeln!(buf, "{}", json_line(record))
+}
+
+fn json_line(record: &Record) -> String {
+    json!({
+        "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        "level": record.level().to_string(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod format {
+    use super::*;
+    use log::Level;
+    use serde_json::Value;
+
+    #[test]
+    fn test_json_line() {
+        let line = json_line(
+            &Record::builder()
+                .args(format_args!("sentry response: {}", 200))
+                .level(Level::Debug)
+                .target("gtsa::sentry::transport")
+                .build(),
+        );
+        let value: Value = serde_json::from_str(&line).unwrap();
+
+        assert_eq!(value["level"], "DEBUG");
+        assert_eq!(value["target"], "gtsa::sentry::transport");
+        assert_eq!(value["message"], "sentry response: 200");
+        assert!(value["timestamp"].is_string());
+    }
+
+    #[test]
+    fn test_settings_parsing() {
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert!("xml".parse::<LogFormat>().is_err());
+
+        assert!("info".parse::<LogFilter>().is_ok());
+        assert!("warn,gtsa::sentry=debug".parse::<LogFilter>().is_ok());
+        assert!("gtsa".parse::<LogFilter>().is_ok());
+        assert!("gtsa=loud".parse::<LogFilter>().is_err());
+    }
+}
@@ -1,53 +1,170 @@
 use actix::System;
 use std::env;
+use std::process;
+use structopt::StructOpt;
 
+mod admin;
+mod cli;
+mod config;
 mod gelf;
+mod health;
+mod logging;
+mod metrics;
 mod sentry;
+mod shutdown;
 
+use crate::cli::Opts;
+use crate::config::Config;
 use crate::gelf::gelf_message_processor::GelfPrinterActor;
-use crate::sentry::sentry_processor::SentryProcessorActor;
+use crate::sentry::dsn::Dsn;
+use crate::sentry::routing::SentryRouter;
+use crate::sentry::sentry_processor::{Drain, Flush, SentryProcessorActor};
+use crate::sentry::spool::Spool;
+use crate::shutdown::{new_shutdown, run_until_signal};
+use futures::future::LocalBoxFuture;
 use gelf::gelf_reader::GelfReaderActor;
+use gelf::http_acceptor;
 use gelf::tcp_acceptor;
+use gelf::tls::{new_tls_acceptor, TcpTls};
 use gelf::udp_acceptor;
 use gelf::unpacking::UnPackActor;
+use log::{error, warn};
 use std::sync::Arc;
 
 fn main() {
-    let dsn = env::var("SENTRY_DSN")
-        .unwrap_or_else(|_e| panic!("You must to pass SENTRY_DSN variable from env"));
-    let udp_addr = env::var("UDP_ADDR").unwrap_or_else(|_| "0.0.0.0:8080".to_string());
-    let tcp_addr = env::var("TCP_ADDR").unwrap_or_else(|_| "0.0.0.0:8081".to_string());
-    let system_name = env::var("SYSTEM").unwrap_or_else(|_| "Gelf Mover".to_string());
+    let opts = Opts::from_args();
+    let overrides = opts.overrides();
+    let config = Config::load(opts.config.as_deref(), |key| {
+        overrides.get(key).cloned().or_else(|| env::var(key).ok())
+    })
+    .unwrap_or_else(|errors| {
+        errors
+            .iter()
+            .for_each(|e| eprintln!("invalid config {}", e));
+        process::exit(2)
+    });
+    if opts.check_config {
+        println!("config is valid");
+        return;
+    }
+    logging::init(&config.log_filter, config.log_format);
 
-    let reader_threads: usize = env::var("READER_THREADS")
-        .unwrap_or_else(|_| "1".to_string())
-        .parse()
-        .unwrap();
-    let unpacker_threads: usize = env::var("UNPACKER_THREADS")
-        .unwrap_or_else(|_| "1".to_string())
-        .parse()
-        .unwrap();
-    let max_parallel_chunks: usize = std::env::var("MAX_PARALLEL_CHUNKS")
-        .unwrap_or_else(|_| "500".to_string())
-        .parse()
-        .unwrap();
+    let sentry_spool = config.sentry_spool_dir.as_ref().map(|dir| {
+        Spool::open(dir, config.sentry_spool_max_bytes)
+            .unwrap_or_else(|e| panic!("Failed to open sentry spool {}: {}", dir, e))
+    });
+    let tcp_tls = config.tcp_tls.as_ref().map(|settings| TcpTls {
+        acceptor: new_tls_acceptor(settings)
+            .unwrap_or_else(|e| panic!("Failed to configure tcp tls: {}", e)),
+        handshake_timeout: settings.handshake_timeout,
+    });
 
-    let system = System::new(system_name);
-    let gelf_reader = Arc::new(GelfReaderActor::new(reader_threads));
-    let gelf_unpacker = Arc::new(UnPackActor::new(unpacker_threads));
-    let gelf_sentry_processor = Arc::new(SentryProcessorActor::new(&dsn, reader_threads));
+    let system = System::new(config.system);
+    let gelf_reader = Arc::new(GelfReaderActor::new(
+        config.reader_threads,
+        config.gelf_parse_mode,
+    ));
+    let gelf_unpacker = Arc::new(UnPackActor::new(config.unpacker_threads));
+    let sentry_router = SentryRouter::new(config.sentry_routes, config.sentry_dsn);
+    let sentry_addrs = sentry_router.dsns().iter().map(Dsn::socket_addr).collect();
+    let gelf_sentry_processor = Arc::new(SentryProcessorActor::new(
+        sentry_router,
+        config.reader_threads,
+        config.sentry_endpoint,
+        config.sentry_retry_policy,
+        sentry_spool,
+        config.sentry_queue_policy,
+        config.sentry_event_mapping,
+    ));
     let _gelf_printer = GelfPrinterActor::new();
-    actix::spawn(udp_acceptor::new_udp_acceptor(
-        udp_addr,
-        Arc::clone(&gelf_sentry_processor),
-        Arc::clone(&gelf_reader),
+    let (shutdown_trigger, shutdown) = new_shutdown();
+    actix::spawn({
+        let shutdown = shutdown.clone();
+        let gelf_sentry_processor = Arc::clone(&gelf_sentry_processor);
+        async move {
+            shutdown.wait().await;
+            health::shutdown_started();
+            gelf_sentry_processor.do_send(Drain);
+        }
+    });
+    let mut acceptors: Vec<LocalBoxFuture<'static, ()>> = Vec::new();
+    if config.udp_enabled {
+        health::listener_enabled("udp");
+        acceptors.push(Box::pin(udp_acceptor::new_udp_acceptor(
+            config.udp_addr,
+            Arc::clone(&gelf_sentry_processor),
+            Arc::clone(&gelf_reader),
+            Arc::clone(&gelf_unpacker),
+            config.udp_settings,
+            shutdown.clone(),
+        )));
+    }
+    if config.tcp_enabled {
+        health::listener_enabled("tcp");
+        acceptors.push(Box::pin(tcp_acceptor::new_tcp_acceptor(
+            config.tcp_addr,
+            Arc::clone(&gelf_sentry_processor),
+            Arc::clone(&gelf_reader),
+            config.tcp_max_frame_size,
+            config.tcp_split_newline,
+            tcp_tls,
+            shutdown.clone(),
+        )));
+    }
+    if config.http_enabled {
+        health::listener_enabled("http");
+        acceptors.push(Box::pin(http_acceptor::new_http_acceptor(
+            config.http_addr,
+            Arc::clone(&gelf_sentry_processor),
+            Arc::clone(&gelf_reader),
+            Arc::clone(&gelf_unpacker),
+            config.http_max_body_size,
+            shutdown,
+        )));
+    }
+    let shutdown_timeout = config.shutdown_timeout;
+    actix::spawn(health::resolve_sentry(sentry_addrs));
+    actix::spawn(health::watch_actor(
+        "reader",
+        gelf_reader,
+        config.liveness_timeout,
+    ));
+    actix::spawn(health::watch_actor(
+        "unpacker",
         gelf_unpacker,
-        max_parallel_chunks,
+        config.liveness_timeout,
     ));
-    actix::spawn(tcp_acceptor::new_tcp_acceptor(
-        tcp_addr,
+    actix::spawn(health::watch_actor(
+        "sentry_processor",
         Arc::clone(&gelf_sentry_processor),
-        Arc::clone(&gelf_reader),
+        config.liveness_timeout,
     ));
-    system.run().unwrap();
+    actix::spawn(admin::new_admin_server(config.admin_addr));
+    actix::spawn(async move {
+        let drained = run_until_signal(
+            acceptors,
+            shutdown_trigger,
+            shutdown_timeout,
+            || async move {
+                match gelf_sentry_processor.send(Flush).await {
+                    Ok(Ok(0)) => true,
+                    Ok(Ok(undelivered)) => {
+                        warn!(
+                            "{} events are lost or not delivered to the sentry",
+                            undelivered
+                        );
+                        false
+                    }
+                    _ => false,
+                }
+            },
+        )
+        .await;
+        System::current().stop_with_code(if drained { 0 } else { 1 });
+    });
+
+    if let Err(e) = system.run() {
+        error!("{}", e);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,110 @@
+use lazy_static::lazy_static;
+use log::error;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, Encoder, HistogramVec,
+    IntCounter, IntCounterVec, TextEncoder,
+};
+
+lazy_static! {
+    /// Received gelf packets, udp chunks and tcp frames are counted separately
+    pub static ref PACKETS_RECEIVED: IntCounterVec = register_int_counter_vec!(
+        "gtsa_packets_received_total",
+        "Received gelf packets per transport",
+        &["transport"]
+    )
+    .unwrap();
+    pub static ref CHUNKS_ASSEMBLED: IntCounter = register_int_counter!(
+        "gtsa_chunks_assembled_total",
+        "Chunked udp messages, which are assembled from all of their chunks"
+    )
+    .unwrap();
+    pub static ref CHUNKS_EXPIRED: IntCounter = register_int_counter!(
+        "gtsa_chunks_expired_total",
+        "Chunked udp messages, which are dropped before all of their chunks are received"
+    )
+    .unwrap();
+    pub static ref DECOMPRESSION_FAILURES: IntCounter = register_int_counter!(
+        "gtsa_decompression_failures_total",
+        "Gelf messages, which could not be decompressed"
+    )
+    .unwrap();
+    pub static ref PARSE_FAILURES: IntCounter = register_int_counter!(
+        "gtsa_parse_failures_total",
+        "Gelf messages, which could not be parsed"
+    )
+    .unwrap();
+    pub static ref GELF_COERCIONS: IntCounterVec = register_int_counter_vec!(
+        "gtsa_gelf_coercions_total",
+        "Gelf fields, which are coerced or defaulted by the lenient parse mode",
+        &["field"]
+    )
+    .unwrap();
+    pub static ref EVENTS_DROPPED: IntCounterVec = register_int_counter_vec!(
+        "gtsa_events_dropped_total",
+        "Gelf messages and sentry events, which are dropped because of the limits",
+        &["reason"]
+    )
+    .unwrap();
+    pub static ref TLS_HANDSHAKE_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "gtsa_tls_handshake_failures_total",
+        "Tcp tls connections, which are closed before the handshake is finished",
+        &["reason"]
+    )
+    .unwrap();
+    pub static ref SENTRY_EVENTS_SENT: IntCounterVec = register_int_counter_vec!(
+        "gtsa_sentry_events_sent_total",
+        "Events, which are accepted by sentry",
+        &["dsn"]
+    )
+    .unwrap();
+    pub static ref SENTRY_EVENTS_FAILED: IntCounterVec = register_int_counter_vec!(
+        "gtsa_sentry_events_failed_total",
+        "Events, which are not accepted by sentry after all retries",
+        &["dsn"]
+    )
+    .unwrap();
+    pub static ref SENTRY_EVENTS_RATE_LIMITED: IntCounterVec = register_int_counter_vec!(
+        "gtsa_sentry_events_rate_limited_total",
+        "Sentry requests, which are answered with 429",
+        &["dsn"]
+    )
+    .unwrap();
+    pub static ref SENTRY_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "gtsa_sentry_request_duration_seconds",
+        "Latency of the sentry requests",
+        &["dsn"]
+    )
+    .unwrap();
+}
+
+/// Encode all registered metrics in the prometheus text format
+pub fn encode() -> (String, Vec<u8>) {
+    let encoder = TextEncoder::new();
+    let mut buf = Vec::new();
+    if let Err(e) = encoder.encode(&prometheus::gather(), &mut buf) {
+        error!("metrics encoding error: {}", e);
+    }
+    (encoder.format_type().to_string(), buf)
+}
+
+#[cfg(test)]
+mod registry {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        PACKETS_RECEIVED.with_label_values(&["udp"]).inc();
+        SENTRY_REQUEST_DURATION
+            .with_label_values(&["sentry.io/1"])
+            .observe(0.1);
+
+        let (content_type, buf) = encode();
+        let metrics = String::from_utf8(buf).unwrap();
+
+        assert!(content_type.starts_with("text/plain"));
+        assert!(metrics.contains("gtsa_packets_received_total{transport=\"udp\"}"));
+        assert!(
+            metrics.contains("gtsa_sentry_request_duration_seconds_count{dsn=\"sentry.io/1\"} 1")
+        );
+    }
+}
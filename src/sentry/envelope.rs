@@ -0,0 +1,73 @@
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use serde_json::Result as JsonResult;
+use uuid::Uuid;
+
+/// Content type of the sentry envelope request
+pub const ENVELOPE_CONTENT_TYPE: &str = "application/x-sentry-envelope";
+
+#[derive(Serialize)]
+struct EnvelopeHeader<'a> {
+    event_id: &'a Uuid,
+    dsn: &'a str,
+    sent_at: String,
+}
+
+#[derive(Serialize)]
+struct EnvelopeItemHeader {
+    r#type: &'static str,
+    length: usize,
+}
+
+/// Build envelope with the single event item.
+/// Envelope contains header line with event_id, dsn and sent_at,
+/// item header line and serialized event payload.
+pub fn new_event_envelope(event_id: &Uuid, dsn: &str, event: &[u8]) -> JsonResult<Vec<u8>> {
+    let header = serde_json::to_vec(&EnvelopeHeader {
+        event_id,
+        dsn,
+        sent_at: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+    })?;
+    let item_header = serde_json::to_vec(&EnvelopeItemHeader {
+        r#type: "event",
+        length: event.len(),
+    })?;
+
+    let mut envelope = Vec::with_capacity(header.len() + item_header.len() + event.len() + 3);
+    envelope.extend_from_slice(&header);
+    envelope.push(b'\n');
+    envelope.extend_from_slice(&item_header);
+    envelope.push(b'\n');
+    envelope.extend_from_slice(event);
+    envelope.push(b'\n');
+
+    Ok(envelope)
+}
+
+#[cfg(test)]
+mod encoder {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_event_envelope() {
+        let event_id = Uuid::new_v4();
+        let event = br#"{"level":"error"}"#;
+
+        let envelope = new_event_envelope(&event_id, "https://key@example.org/1", event).unwrap();
+        let lines: Vec<&[u8]> = envelope.split(|b| *b == b'\n').collect();
+        assert_eq!(lines.len(), 4);
+
+        let header: Value = serde_json::from_slice(lines[0]).unwrap();
+        assert_eq!(header["event_id"], event_id.to_string());
+        assert_eq!(header["dsn"], "https://key@example.org/1");
+        assert!(header["sent_at"].is_string());
+
+        let item_header: Value = serde_json::from_slice(lines[1]).unwrap();
+        assert_eq!(item_header["type"], "event");
+        assert_eq!(item_header["length"], event.len());
+
+        assert_eq!(lines[2], &event[..]);
+        assert!(lines[3].is_empty());
+    }
+}
@@ -0,0 +1,428 @@
+use crate::gelf::gelf_reader::{GelfData, GelfDataWrapper, GelfLevel};
+use crate::sentry::mapping::EventMapping;
+use crate::sentry::stacktrace::{self, ParsedException};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+/// Additional fields, which mean that the gelf message contains an exception
+const EXCEPTION_FIELDS: [&str; 3] = ["exception_type", "exception_message", "exception"];
+/// Additional fields with the stack trace, they are checked before the full message
+const STACKTRACE_FIELDS: [&str; 2] = ["stack_trace", "exception"];
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SentryLevel {
+    Fatal,
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+impl From<GelfLevel> for SentryLevel {
+    fn from(level: GelfLevel) -> Self {
+        match level {
+            GelfLevel::Emergency => SentryLevel::Fatal,
+            GelfLevel::Alert => SentryLevel::Error,
+            GelfLevel::Critical => SentryLevel::Error,
+            GelfLevel::Error => SentryLevel::Error,
+            GelfLevel::Warning => SentryLevel::Warning,
+            GelfLevel::Notice => SentryLevel::Warning,
+            GelfLevel::Informational => SentryLevel::Info,
+            GelfLevel::Debug => SentryLevel::Debug,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SentryLogEntry {
+    pub formatted: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SentryMechanism {
+    pub r#type: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct SentryFrame {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lineno: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub colno: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_line: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_app: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SentryStacktrace {
+    pub frames: Vec<SentryFrame>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SentryExceptionValue {
+    pub r#type: String,
+    pub value: String,
+    pub mechanism: SentryMechanism,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stacktrace: Option<SentryStacktrace>,
+}
+
+impl From<ParsedException> for SentryExceptionValue {
+    fn from(parsed: ParsedException) -> Self {
+        SentryExceptionValue {
+            r#type: parsed.r#type,
+            value: parsed.value,
+            mechanism: SentryMechanism {
+                r#type: "gelf".to_string(),
+            },
+            stacktrace: Some(SentryStacktrace {
+                frames: parsed.frames,
+            })
+            .filter(|stacktrace| !stacktrace.frames.is_empty()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SentryException {
+    pub values: Vec<SentryExceptionValue>,
+}
+
+/// Thread of the event, it carries the stack frame of the message without exception
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SentryThread {
+    pub stacktrace: SentryStacktrace,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SentryThreads {
+    pub values: Vec<SentryThread>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SentryEvent {
+    pub event_id: Uuid,
+    pub server_name: String,
+    pub timestamp: f64,
+    pub level: SentryLevel,
+    pub logentry: SentryLogEntry,
+    /// Gelf facility
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logger: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release: Option<String>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub tags: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub extra: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub user: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub contexts: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fingerprint: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exception: Option<SentryException>,
+    /// Gelf file and line, when the message has no exception
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threads: Option<SentryThreads>,
+}
+
+impl SentryEvent {
+    /// Convert gelf message to the sentry event.
+    /// Fields are sent by the mapping rules, the rest additional fields are sent as extra.
+    /// Exception is sent only when the message has it, stack trace is parsed from
+    /// `_stack_trace`, `_exception` or `full_message` fields.
+    pub fn new(gd: GelfDataWrapper, mapping: &EventMapping) -> SentryEvent {
+        let mut gelf = gd.into_gelf();
+        let mapped = mapping.apply(&mut gelf);
+        let GelfData {
+            host,
+            level,
+            short_message,
+            full_message,
+            timestamp,
+            facility,
+            file,
+            line,
+            mut meta,
+            mechanism_data,
+            coercions,
+            ..
+        } = gelf;
+
+        let parsed = STACKTRACE_FIELDS
+            .iter()
+            .filter_map(|field| meta.get(*field)?.as_str())
+            .chain(full_message.as_deref())
+            .find_map(stacktrace::parse);
+        let has_exception =
+            parsed.is_some() || EXCEPTION_FIELDS.iter().any(|f| meta.contains_key(*f));
+        let (exception, threads) = match (has_exception, file) {
+            (true, file) => {
+                let mut values: Vec<SentryExceptionValue> = match parsed {
+                    Some(parsed) => parsed.into_iter().map(SentryExceptionValue::from).collect(),
+                    None => vec![SentryExceptionValue {
+                        r#type: "Error".to_string(),
+                        value: meta
+                            .get("exception")
+                            .and_then(Value::as_str)
+                            .and_then(first_line)
+                            .unwrap_or_else(|| short_message.clone()),
+                        mechanism: SentryMechanism {
+                            r#type: "gelf".to_string(),
+                        },
+                        stacktrace: file.map(|file| gelf_stacktrace(file, line)),
+                    }],
+                };
+                // explicit fields describe the outermost exception
+                if let Some(outermost) = values.last_mut() {
+                    if let Some(r#type) = take_string(&mut meta, "exception_type") {
+                        outermost.r#type = r#type;
+                    }
+                    if let Some(value) = take_string(&mut meta, "exception_message") {
+                        outermost.value = value;
+                    }
+                }
+                (Some(SentryException { values }), None)
+            }
+            (false, Some(file)) => (
+                None,
+                Some(SentryThreads {
+                    values: vec![SentryThread {
+                        stacktrace: gelf_stacktrace(file, line),
+                    }],
+                }),
+            ),
+            (false, None) => (None, None),
+        };
+
+        // full message without stack trace is the detailed log message
+        let (formatted, full_message) = match full_message {
+            Some(full_message) if exception.is_none() => {
+                (join_messages(short_message, full_message), None)
+            }
+            full_message => (short_message, full_message),
+        };
+
+        let mut extra: Map<String, Value> = meta
+            .into_iter()
+            .chain(mechanism_data)
+            .chain(mapped.extra)
+            .collect();
+        if let Some(full_message) = full_message {
+            extra.insert("full_message".to_string(), Value::String(full_message));
+        }
+        if !coercions.is_empty() {
+            extra.insert("gelf_coercions".to_string(), Value::from(coercions));
+        }
+
+        SentryEvent {
+            event_id: Uuid::new_v4(),
+            server_name: host,
+            timestamp,
+            level: SentryLevel::from(level),
+            logentry: SentryLogEntry { formatted },
+            logger: facility,
+            transaction: mapped.transaction,
+            environment: mapped.environment,
+            release: mapped.release,
+            tags: mapped.tags,
+            extra,
+            user: mapped.user,
+            contexts: mapped.contexts,
+            fingerprint: mapped.fingerprint,
+            exception,
+            threads,
+        }
+    }
+}
+
+/// Stack trace of the single frame from the gelf `file` and `line` fields
+fn gelf_stacktrace(file: String, line: Option<u64>) -> SentryStacktrace {
+    SentryStacktrace {
+        frames: vec![SentryFrame {
+            filename: Some(file),
+            lineno: line,
+            ..SentryFrame::default()
+        }],
+    }
+}
+
+/// Full message often repeats the short one, so it is appended only when it differs
+fn join_messages(short_message: String, full_message: String) -> String {
+    if full_message.starts_with(&short_message) {
+        full_message
+    } else {
+        format!("{}\n\n{}", short_message, full_message)
+    }
+}
+
+fn take_string(meta: &mut Map<String, Value>, field: &str) -> Option<String> {
+    match meta.remove(field)? {
+        Value::String(value) => Some(value),
+        Value::Null => None,
+        value => Some(value.to_string()),
+    }
+}
+
+fn first_line(text: &str) -> Option<String> {
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod mapping {
+    use super::*;
+    use crate::gelf::gelf_reader::ParseMode;
+    use crate::sentry::mapping::{FieldRule, MappingTarget};
+
+    fn event(gelf: &[u8], tags: &[&str]) -> SentryEvent {
+        let mapping = EventMapping::new(
+            tags.iter()
+                .map(|tag| FieldRule::new(tag, MappingTarget::Tags))
+                .collect(),
+        );
+        SentryEvent::new(
+            GelfDataWrapper::from_slice(gelf, ParseMode::Strict).unwrap(),
+            &mapping,
+        )
+    }
+
+    #[test]
+    fn test_message() {
+        let s = event(
+            br#"{
+                "version":"1.0",
+                "host":"example.org",
+                "short_message":"A short message",
+                "full_message":"Backtrace here\n\nmore stuff",
+                "level":5,
+                "facility":"billing",
+                "file":"/srv/app/billing.rb",
+                "line":42,
+                "_service":"api",
+                "_port":8080,
+                "_environment":"production",
+                "_release":"1.2.3",
+                "_some_info":"foo",
+                "timestamp":1582213226
+            }"#,
+            &["_service", "_port", "_missing"],
+        );
+
+        assert_eq!(s.level, SentryLevel::Warning);
+        assert_eq!(s.server_name, "example.org");
+        assert_eq!(
+            s.logentry.formatted,
+            "A short message\n\nBacktrace here\n\nmore stuff"
+        );
+        assert_eq!(s.logger.as_deref(), Some("billing"));
+        let frame = &s.threads.as_ref().unwrap().values[0].stacktrace.frames[0];
+        assert_eq!(frame.filename.as_deref(), Some("/srv/app/billing.rb"));
+        assert_eq!(frame.lineno, Some(42));
+        assert_eq!(s.environment.as_deref(), Some("production"));
+        assert_eq!(s.release.as_deref(), Some("1.2.3"));
+        assert_eq!(s.tags.len(), 2);
+        assert_eq!(s.tags["service"], "api");
+        assert_eq!(s.tags["port"], "8080");
+        assert_eq!(s.extra["some_info"], "foo");
+        assert!(!s.extra.contains_key("full_message"));
+        assert!(!s.extra.contains_key("service"));
+        assert!(s.exception.is_none());
+    }
+
+    #[test]
+    fn test_gelf_1_0_fields() {
+        let s = event(
+            br#"{
+                "version":"1.0",
+                "host":"example.org",
+                "short_message":"Payment failed",
+                "full_message":"Payment failed\ncard 4242 is expired",
+                "level":3,
+                "facility":"billing",
+                "file":"/srv/app/billing.rb",
+                "timestamp":1582213226
+            }"#,
+            &[],
+        );
+
+        assert_eq!(s.level, SentryLevel::Error);
+        assert_eq!(s.logger.as_deref(), Some("billing"));
+        assert_eq!(s.logentry.formatted, "Payment failed\ncard 4242 is expired");
+        let frame = &s.threads.as_ref().unwrap().values[0].stacktrace.frames[0];
+        assert_eq!(frame.filename.as_deref(), Some("/srv/app/billing.rb"));
+        assert!(frame.lineno.is_none());
+        assert!(s.extra.is_empty());
+        assert!(s.exception.is_none());
+    }
+
+    #[test]
+    fn test_exception() {
+        let s = event(
+            br#"{
+                "version":"1.1",
+                "host":"example.org",
+                "short_message":"Payment failed",
+                "level":3,
+                "file":"Billing.java",
+                "line":12,
+                "_exception_type":"java.lang.IllegalStateException",
+                "_exception":"\njava.lang.IllegalStateException: card expired\n\tat Billing.pay(Billing.java:12)",
+                "timestamp":1582213226
+            }"#,
+            &[],
+        );
+
+        let exception = &s.exception.as_ref().unwrap().values[0];
+        assert_eq!(exception.r#type, "java.lang.IllegalStateException");
+        assert_eq!(exception.value, "card expired");
+        let frame = &exception.stacktrace.as_ref().unwrap().frames[0];
+        assert_eq!(frame.function.as_deref(), Some("pay"));
+        assert_eq!(frame.filename.as_deref(), Some("Billing.java"));
+        assert_eq!(frame.lineno, Some(12));
+        assert!(s.threads.is_none());
+        assert!(s.extra.contains_key("exception"));
+    }
+
+    #[test]
+    fn test_full_message_stacktrace() {
+        let s = event(
+            br#"{
+                "version":"1.1",
+                "host":"example.org",
+                "short_message":"Payment failed",
+                "full_message":"Traceback (most recent call last):\n  File \"/app/billing.py\", line 12, in pay\n    charge(card)\nValueError: card expired",
+                "level":3,
+                "timestamp":1582213226
+            }"#,
+            &[],
+        );
+
+        let exception = &s.exception.as_ref().unwrap().values[0];
+        assert_eq!(exception.r#type, "ValueError");
+        assert_eq!(exception.value, "card expired");
+        let frame = &exception.stacktrace.as_ref().unwrap().frames[0];
+        assert_eq!(frame.function.as_deref(), Some("pay"));
+        assert_eq!(frame.in_app, Some(true));
+        assert!(s.extra.contains_key("full_message"));
+        assert_eq!(s.logentry.formatted, "Payment failed");
+    }
+}
@@ -0,0 +1,399 @@
+use crate::gelf::gelf_reader::GelfData;
+use serde::de::Error as DeError;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+
+/// Part of the sentry event, which is filled by the gelf field
+#[derive(Clone, Debug, PartialEq)]
+pub enum MappingTarget {
+    Tags,
+    Extra,
+    User,
+    /// Context with the name, e.g. `contexts.os`
+    Contexts(String),
+    Fingerprint,
+    Transaction,
+    Release,
+    Environment,
+    /// Field is not sent to the sentry
+    Drop,
+}
+
+impl MappingTarget {
+    /// Targets, which are maps, so the field could be renamed in them
+    pub fn is_keyed(&self) -> bool {
+        matches!(
+            self,
+            MappingTarget::Tags
+                | MappingTarget::Extra
+                | MappingTarget::User
+                | MappingTarget::Contexts(_)
+        )
+    }
+}
+
+impl FromStr for MappingTarget {
+    type Err = MappingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tags" => Ok(MappingTarget::Tags),
+            "extra" => Ok(MappingTarget::Extra),
+            "user" => Ok(MappingTarget::User),
+            "fingerprint" => Ok(MappingTarget::Fingerprint),
+            "transaction" => Ok(MappingTarget::Transaction),
+            "release" => Ok(MappingTarget::Release),
+            "environment" => Ok(MappingTarget::Environment),
+            "drop" => Ok(MappingTarget::Drop),
+            _ => match s.strip_prefix("contexts.") {
+                Some(name) if !name.is_empty() => Ok(MappingTarget::Contexts(name.to_string())),
+                _ => Err(MappingError::UnknownTarget(s.to_string())),
+            },
+        }
+    }
+}
+
+impl Display for MappingTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            MappingTarget::Tags => write!(f, "tags"),
+            MappingTarget::Extra => write!(f, "extra"),
+            MappingTarget::User => write!(f, "user"),
+            MappingTarget::Contexts(name) => write!(f, "contexts.{}", name),
+            MappingTarget::Fingerprint => write!(f, "fingerprint"),
+            MappingTarget::Transaction => write!(f, "transaction"),
+            MappingTarget::Release => write!(f, "release"),
+            MappingTarget::Environment => write!(f, "environment"),
+            MappingTarget::Drop => write!(f, "drop"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for MappingTarget {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Rule, which moves gelf `field` to the `target` of the sentry event.
+/// Additional fields are passed with `_` prefix, e.g. `_service`, they are removed from the extra.
+/// `rename` is the key in the keyed targets, the field name without `_` is used by default.
+/// `default` is used, when the message has no field.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FieldRule {
+    pub field: String,
+    pub target: MappingTarget,
+    #[serde(default)]
+    pub rename: Option<String>,
+    #[serde(default)]
+    pub default: Option<Value>,
+}
+
+impl FieldRule {
+    pub fn new(field: &str, target: MappingTarget) -> FieldRule {
+        FieldRule {
+            field: field.to_string(),
+            target,
+            rename: None,
+            default: None,
+        }
+    }
+
+    fn key(&self) -> String {
+        self.rename
+            .clone()
+            .unwrap_or_else(|| self.field.trim_start_matches('_').to_string())
+    }
+}
+
+/// Fields of the sentry event, which are filled by the mapping rules
+#[derive(Debug, Default)]
+pub struct MappedFields {
+    pub tags: Map<String, Value>,
+    pub extra: Map<String, Value>,
+    pub user: Map<String, Value>,
+    pub contexts: Map<String, Value>,
+    pub fingerprint: Vec<String>,
+    pub transaction: Option<String>,
+    pub release: Option<String>,
+    pub environment: Option<String>,
+}
+
+/// Rules of the gelf message conversion to the sentry event.
+/// Rules are applied in order, the first value wins for the single value targets.
+/// `_environment` and `_release` fields are mapped after the configured rules.
+#[derive(Clone, Debug)]
+pub struct EventMapping {
+    pub rules: Vec<FieldRule>,
+}
+
+impl EventMapping {
+    pub fn new(mut rules: Vec<FieldRule>) -> EventMapping {
+        rules.push(FieldRule::new("_environment", MappingTarget::Environment));
+        rules.push(FieldRule::new("_release", MappingTarget::Release));
+        EventMapping { rules }
+    }
+
+    /// Collect mapped fields of the gelf message and remove mapped additional fields from it
+    pub fn apply(&self, gelf: &mut GelfData) -> MappedFields {
+        let mut mapped = MappedFields::default();
+        for rule in &self.rules {
+            let value = match gelf.value(&rule.field).or_else(|| rule.default.clone()) {
+                None | Some(Value::Null) => continue,
+                Some(value) => value,
+            };
+            match &rule.target {
+                MappingTarget::Tags => {
+                    mapped
+                        .tags
+                        .insert(rule.key(), Value::String(to_string(value)));
+                }
+                MappingTarget::Extra => {
+                    mapped.extra.insert(rule.key(), value);
+                }
+                MappingTarget::User => {
+                    mapped.user.insert(rule.key(), value);
+                }
+                MappingTarget::Contexts(name) => {
+                    if let Value::Object(context) = mapped
+                        .contexts
+                        .entry(name.as_str())
+                        .or_insert_with(|| Value::Object(Map::new()))
+                    {
+                        context.insert(rule.key(), value);
+                    }
+                }
+                MappingTarget::Fingerprint => match value {
+                    Value::Array(values) => {
+                        mapped.fingerprint.extend(values.into_iter().map(to_string))
+                    }
+                    value => mapped.fingerprint.push(to_string(value)),
+                },
+                MappingTarget::Transaction => {
+                    mapped.transaction.get_or_insert_with(|| to_string(value));
+                }
+                MappingTarget::Release => {
+                    mapped.release.get_or_insert_with(|| to_string(value));
+                }
+                MappingTarget::Environment => {
+                    mapped.environment.get_or_insert_with(|| to_string(value));
+                }
+                MappingTarget::Drop => {}
+            }
+        }
+
+        for rule in &self.rules {
+            match rule.field.strip_prefix('_') {
+                Some(field) => {
+                    gelf.meta.remove(field);
+                }
+                // standard fields are still sent, when they are mapped somewhere
+                None if rule.target == MappingTarget::Drop => gelf.remove(&rule.field),
+                None => {
+                    gelf.mechanism_data.remove(&rule.field);
+                }
+            }
+        }
+        mapped
+    }
+}
+
+impl Default for EventMapping {
+    fn default() -> Self {
+        EventMapping::new(Vec::new())
+    }
+}
+
+/// Sentry tags, fingerprint and attributes are strings
+fn to_string(value: Value) -> String {
+    match value {
+        Value::String(value) => value,
+        value => value.to_string(),
+    }
+}
+
+/// Error, which returned when mapping rule is invalid
+#[derive(Debug, PartialEq)]
+pub enum MappingError {
+    UnknownTarget(String),
+    UnexpectedRename(String, MappingTarget),
+    RequiredDrop(String),
+}
+
+impl Display for MappingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            MappingError::UnknownTarget(target) => write!(
+                f,
+                "target must be tags, extra, user, contexts.<name>, fingerprint, transaction, release, environment or drop, but {} is passed",
+                target
+            ),
+            MappingError::UnexpectedRename(field, target) => write!(
+                f,
+                "field {} could not be renamed in the {} target",
+                field, target
+            ),
+            MappingError::RequiredDrop(field) => {
+                write!(f, "required gelf field {} could not be dropped", field)
+            }
+        }
+    }
+}
+
+impl Error for MappingError {}
+
+/// Gelf fields, which are always sent to the sentry event
+const REQUIRED_FIELDS: [&str; 5] = ["host", "short_message", "level", "timestamp", "version"];
+
+/// Check, that the rule could be applied
+pub fn validate(rule: &FieldRule) -> Result<(), MappingError> {
+    match (&rule.rename, &rule.target) {
+        (Some(_), target) if !target.is_keyed() => Err(MappingError::UnexpectedRename(
+            rule.field.clone(),
+            rule.target.clone(),
+        )),
+        (_, MappingTarget::Drop) if REQUIRED_FIELDS.contains(&rule.field.as_str()) => {
+            Err(MappingError::RequiredDrop(rule.field.clone()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod rules {
+    use super::*;
+    use crate::gelf::gelf_reader::{GelfDataWrapper, ParseMode};
+
+    fn gelf() -> GelfData {
+        GelfDataWrapper::from_slice(
+            br#"{
+                "version":"1.1",
+                "host":"example.org",
+                "short_message":"A short message",
+                "level":5,
+                "facility":"billing",
+                "_env":"staging",
+                "_environment":"production",
+                "_traceId":"abc",
+                "_user_id":42,
+                "_os":"linux",
+                "_route":"/pay",
+                "_password":"secret",
+                "_keep":"foo",
+                "timestamp":1582213226
+            }"#,
+            ParseMode::Strict,
+        )
+        .unwrap()
+        .into_gelf()
+    }
+
+    fn rule(field: &str, target: &str, rename: Option<&str>) -> FieldRule {
+        FieldRule {
+            field: field.to_string(),
+            target: target.parse().unwrap(),
+            rename: rename.map(str::to_string),
+            default: None,
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut region = rule("_region", "tags", None);
+        region.default = Some(Value::from("eu"));
+        let mapping = EventMapping::new(vec![
+            rule("_env", "environment", None),
+            rule("_traceId", "tags", Some("trace_id")),
+            region,
+            rule("facility", "tags", None),
+            rule("_user_id", "user", Some("id")),
+            rule("_os", "contexts.os", Some("name")),
+            rule("_route", "transaction", None),
+            rule("_route", "fingerprint", None),
+            rule("facility", "fingerprint", None),
+            rule("_password", "drop", None),
+        ]);
+
+        let mut gelf = gelf();
+        let mapped = mapping.apply(&mut gelf);
+
+        assert_eq!(mapped.environment.as_deref(), Some("staging"));
+        assert_eq!(mapped.tags["trace_id"], "abc");
+        assert_eq!(mapped.tags["region"], "eu");
+        assert_eq!(mapped.tags["facility"], "billing");
+        assert_eq!(mapped.user["id"], 42);
+        assert_eq!(mapped.contexts["os"]["name"], "linux");
+        assert_eq!(mapped.transaction.as_deref(), Some("/pay"));
+        assert_eq!(mapped.fingerprint, vec!["/pay", "billing"]);
+        assert!(mapped.release.is_none());
+
+        let mut left: Vec<&String> = gelf.meta.keys().collect();
+        left.sort();
+        assert_eq!(left, vec!["keep"]);
+        assert_eq!(gelf.facility.as_deref(), Some("billing"));
+    }
+
+    #[test]
+    fn test_rules() {
+        assert_eq!(
+            "contexts.runtime".parse(),
+            Ok(MappingTarget::Contexts("runtime".to_string()))
+        );
+        assert!("contexts.".parse::<MappingTarget>().is_err());
+        assert!("labels".parse::<MappingTarget>().is_err());
+
+        let rules: Vec<FieldRule> = serde_json::from_str(
+            r#"[{"field": "_env", "target": "environment"}, {"field": "_v", "target": "tags", "rename": "version", "default": "1"}]"#,
+        )
+        .unwrap();
+        assert_eq!(rules[1].key(), "version");
+        assert!(rules.iter().all(|rule| validate(rule).is_ok()));
+        assert!(serde_json::from_str::<FieldRule>(r#"{"field": "_a", "target": "tag"}"#).is_err());
+        assert!(serde_json::from_str::<FieldRule>(
+            r#"{"field": "_a", "target": "tags", "name": "b"}"#
+        )
+        .is_err());
+        assert_eq!(
+            validate(&rule("_env", "environment", Some("env"))),
+            Err(MappingError::UnexpectedRename(
+                "_env".to_string(),
+                MappingTarget::Environment
+            ))
+        );
+    }
+
+    #[test]
+    fn test_drop_standard() {
+        let mapping = EventMapping::new(vec![
+            rule("facility", "drop", None),
+            rule("file", "drop", None),
+            rule("line", "drop", None),
+            rule("full_message", "drop", None),
+        ]);
+        assert!(mapping.rules.iter().all(|rule| validate(rule).is_ok()));
+
+        let mut gelf = gelf();
+        gelf.full_message = Some("Backtrace here".to_string());
+        gelf.file = Some("billing.py".to_string());
+        gelf.line = Some(12);
+        mapping.apply(&mut gelf);
+        assert!(gelf.facility.is_none());
+        assert!(gelf.file.is_none());
+        assert!(gelf.line.is_none());
+        assert!(gelf.full_message.is_none());
+
+        assert_eq!(
+            validate(&rule("short_message", "drop", None)),
+            Err(MappingError::RequiredDrop("short_message".to_string()))
+        );
+    }
+}
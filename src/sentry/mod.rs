@@ -1 +1,10 @@
+pub mod dsn;
+pub mod envelope;
+pub mod event;
+pub mod mapping;
+pub mod queue;
+pub mod routing;
 pub mod sentry_processor;
+pub mod spool;
+pub mod stacktrace;
+pub mod transport;
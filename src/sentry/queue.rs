@@ -0,0 +1,184 @@
+use futures::channel::oneshot::{channel, Receiver, Sender};
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+
+/// What to do with the new event, when the send queue is full
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowPolicy {
+    /// New event is dropped
+    DropNewest,
+    /// Oldest queued event is dropped to make room for the new one
+    DropOldest,
+    /// Sender waits until the queue has room, e.g. tcp reader stops reading its connection.
+    /// New event is dropped, when as many senders wait as the queue capacity.
+    Block,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop_newest" => Ok(OverflowPolicy::DropNewest),
+            "drop_oldest" => Ok(OverflowPolicy::DropOldest),
+            "block" => Ok(OverflowPolicy::Block),
+            policy => Err(format!(
+                "overflow policy must be drop_newest, drop_oldest or block, but {} is passed",
+                policy
+            )),
+        }
+    }
+}
+
+impl Display for OverflowPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            OverflowPolicy::DropNewest => write!(f, "drop_newest"),
+            OverflowPolicy::DropOldest => write!(f, "drop_oldest"),
+            OverflowPolicy::Block => write!(f, "block"),
+        }
+    }
+}
+
+/// Limits of the sentry send queue
+#[derive(Clone, Copy, Debug)]
+pub struct QueuePolicy {
+    pub capacity: usize,
+    pub max_in_flight: usize,
+    pub overflow: OverflowPolicy,
+}
+
+/// Result of the pushing into the send queue
+pub enum Enqueued<T> {
+    Queued,
+    /// Queue is full and one event is dropped by the overflow policy,
+    /// it is the new event or the oldest one
+    Dropped(T),
+    /// Queue is full, receiver is resolved when the event is moved into the queue
+    Blocked(Receiver<()>),
+}
+
+/// Bounded queue of the events, which are waiting for sending
+pub struct SendQueue<T> {
+    jobs: VecDeque<T>,
+    /// Events of the waiting senders, it is bounded by the capacity too
+    blocked: VecDeque<(T, Sender<()>)>,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    dropped: u64,
+}
+
+impl<T> SendQueue<T> {
+    pub fn new(capacity: usize, overflow: OverflowPolicy) -> SendQueue<T> {
+        SendQueue {
+            jobs: VecDeque::with_capacity(capacity),
+            blocked: VecDeque::new(),
+            capacity: capacity.max(1),
+            overflow,
+            dropped: 0,
+        }
+    }
+
+    pub fn push(&mut self, job: T) -> Enqueued<T> {
+        if self.jobs.len() < self.capacity {
+            self.jobs.push_back(job);
+            return Enqueued::Queued;
+        }
+
+        let dropped = match self.overflow {
+            OverflowPolicy::DropNewest => job,
+            OverflowPolicy::DropOldest => {
+                self.jobs.push_back(job);
+                self.jobs.pop_front().unwrap()
+            }
+            OverflowPolicy::Block if self.blocked.len() < self.capacity => {
+                let (sender, receiver) = channel();
+                self.blocked.push_back((job, sender));
+                return Enqueued::Blocked(receiver);
+            }
+            OverflowPolicy::Block => job,
+        };
+        self.dropped += 1;
+        Enqueued::Dropped(dropped)
+    }
+
+    /// Take the oldest event, first blocked sender is released into the freed place
+    pub fn pop(&mut self) -> Option<T> {
+        let job = self.jobs.pop_front()?;
+        if let Some((blocked, sender)) = self.blocked.pop_front() {
+            self.jobs.push_back(blocked);
+            let _ = sender.send(());
+        }
+        Some(job)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty() && self.blocked.is_empty()
+    }
+
+    /// Count of the events, which were dropped by the overflow policy
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod policies {
+    use super::*;
+
+    fn fill(overflow: OverflowPolicy) -> SendQueue<u32> {
+        let mut queue = SendQueue::new(2, overflow);
+        assert!(matches!(queue.push(1), Enqueued::Queued));
+        assert!(matches!(queue.push(2), Enqueued::Queued));
+        queue
+    }
+
+    #[test]
+    fn test_drop_policies() {
+        let mut queue = fill(OverflowPolicy::DropNewest);
+        assert!(matches!(queue.push(3), Enqueued::Dropped(3)));
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), None);
+
+        let mut queue = fill(OverflowPolicy::DropOldest);
+        assert!(matches!(queue.push(3), Enqueued::Dropped(1)));
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[actix_rt::test]
+    async fn test_block_policy() {
+        let mut queue = fill(OverflowPolicy::Block);
+        let receiver = match queue.push(3) {
+            Enqueued::Blocked(receiver) => receiver,
+            _ => panic!("sender must be blocked"),
+        };
+        assert!(matches!(queue.push(4), Enqueued::Blocked(_)));
+        assert_eq!(queue.dropped(), 0);
+        // waiting senders are bounded by the capacity
+        assert!(matches!(queue.push(5), Enqueued::Dropped(5)));
+        assert_eq!(queue.dropped(), 1);
+
+        assert_eq!(queue.pop(), Some(1));
+        assert!(receiver.await.is_ok());
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(3));
+        assert_eq!(queue.pop(), Some(4));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_policy_parsing() {
+        assert_eq!("block".parse(), Ok(OverflowPolicy::Block));
+        assert_eq!(
+            OverflowPolicy::DropOldest.to_string().parse(),
+            Ok(OverflowPolicy::DropOldest)
+        );
+        assert!("drop_all".parse::<OverflowPolicy>().is_err());
+    }
+}
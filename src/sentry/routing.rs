@@ -0,0 +1,119 @@
+use crate::gelf::gelf_reader::GelfDataWrapper;
+use crate::sentry::dsn::Dsn;
+use serde::Deserialize;
+
+/// Route, which sends gelf messages with `field` equal to `value` to the `dsn`.
+/// Additional fields are passed with `_` prefix, e.g. `_service`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SentryRoute {
+    pub field: String,
+    pub value: String,
+    pub dsn: Dsn,
+}
+
+/// Routing table, which selects sentry dsn for the gelf message.
+/// Routes are checked in order, default dsn is used when no one route is matched.
+pub struct SentryRouter {
+    routes: Vec<(String, String, usize)>,
+    default: Option<usize>,
+    dsns: Vec<Dsn>,
+}
+
+impl SentryRouter {
+    pub fn new(routes: Vec<SentryRoute>, default: Option<Dsn>) -> SentryRouter {
+        let mut router = SentryRouter {
+            routes: Vec::with_capacity(routes.len()),
+            default: None,
+            dsns: Vec::new(),
+        };
+
+        for SentryRoute { field, value, dsn } in routes {
+            let target = router.target(dsn);
+            router.routes.push((field, value, target));
+        }
+        router.default = default.map(|dsn| router.target(dsn));
+
+        router
+    }
+
+    /// Returns index of the dsn for the gelf message
+    pub fn route(&self, gelf: &GelfDataWrapper) -> Option<usize> {
+        self.routes
+            .iter()
+            .find(|(field, value, _)| gelf.field(field).as_ref() == Some(value))
+            .map(|(_, _, target)| *target)
+            .or(self.default)
+    }
+
+    /// Returns all unique dsns of the routing table
+    pub fn dsns(&self) -> &[Dsn] {
+        &self.dsns
+    }
+
+    fn target(&mut self, dsn: Dsn) -> usize {
+        match self.dsns.iter().position(|d| *d == dsn) {
+            Some(target) => target,
+            None => {
+                self.dsns.push(dsn);
+                self.dsns.len() - 1
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod router {
+    use super::*;
+    use crate::gelf::gelf_reader::ParseMode;
+
+    fn gelf(service: &str) -> GelfDataWrapper {
+        GelfDataWrapper::from_slice(
+            format!(
+                r#"{{
+                    "version":"1.1",
+                    "host":"example.org",
+                    "short_message":"A short message",
+                    "level":5,
+                    "_service":"{}",
+                    "timestamp":1582213226
+                }}"#,
+                service
+            )
+            .as_bytes(),
+            ParseMode::Strict,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_route() {
+        let routes: Vec<SentryRoute> = serde_json::from_str(
+            r#"[
+                {"field":"_service","value":"billing","dsn":"https://billing@example.org/1"},
+                {"field":"_service","value":"api","dsn":"https://api@example.org/2"},
+                {"field":"host","value":"example.org","dsn":"https://billing@example.org/1"}
+            ]"#,
+        )
+        .unwrap();
+        let router = SentryRouter::new(
+            routes.clone(),
+            Some("https://default@example.org/3".parse().unwrap()),
+        );
+
+        assert_eq!(router.dsns().len(), 3);
+        assert_eq!(router.route(&gelf("billing")), Some(0));
+        assert_eq!(router.route(&gelf("api")), Some(1));
+        assert_eq!(router.route(&gelf("other")), Some(0));
+
+        let router = SentryRouter::new(routes[..2].to_vec(), None);
+        assert_eq!(router.route(&gelf("other")), None);
+    }
+
+    #[test]
+    fn test_invalid_route() {
+        let routes: Result<Vec<SentryRoute>, _> = serde_json::from_str(
+            r#"[{"field":"_service","value":"billing","dsn":"https://example.org/1"}]"#,
+        );
+        assert!(routes.is_err());
+    }
+}
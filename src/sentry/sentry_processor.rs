@@ -1,317 +1,704 @@
+use actix::dev::{MessageResponse, ResponseChannel};
+use actix::fut::wrap_future;
 use actix::prelude::*;
-use scan_fmt::scan_fmt;
-use serde::{Deserialize, Serialize};
-use serde_json::{Map, Value};
+use futures::channel::oneshot::{channel, Canceled, Receiver, Sender};
+use futures::future::ready;
 
 use crate::gelf::gelf_message_processor::GelfProcessorMessage;
-use crate::gelf::gelf_reader::{GelfData, GelfDataWrapper, GelfLevel};
-use reqwest::Client;
-use std::borrow::Cow;
-use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::health::Ping;
+use crate::metrics::EVENTS_DROPPED;
+use crate::sentry::envelope::{new_event_envelope, ENVELOPE_CONTENT_TYPE};
+use crate::sentry::event::SentryEvent;
+use crate::sentry::mapping::EventMapping;
+use crate::sentry::queue::{Enqueued, QueuePolicy, SendQueue};
+use crate::sentry::routing::SentryRouter;
+use crate::sentry::spool::{
+    Spool, SpoolAck, SpoolActor, SpoolCursor, SpoolLen, SpoolPush, SpoolRead, SpoolRecord,
+};
+use crate::sentry::transport::{RetryPolicy, SendError, SentryTransport};
+use log::{error, warn};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use uuid::Uuid;
 
+/// Interval between replays of the spooled events
+const SPOOL_REPLAY_INTERVAL: Duration = Duration::from_secs(10);
+/// Count of the spooled events, which are read at once by the replay
+const SPOOL_REPLAY_BATCH: usize = 100;
+
 pub struct SentryProcessorActor {
-    dsn: Dsn,
-    client: Client,
+    router: SentryRouter,
+    transports: Vec<SentryTransport>,
+    endpoint: SentryEndpoint,
     prepare_actor: Arc<Addr<PrepareActor>>,
+    spool: Option<Arc<Addr<SpoolActor>>>,
+    delivering: Arc<Mutex<HashSet<Uuid>>>,
+    queue: SendQueue<(usize, SpoolRecord)>,
+    preparing: usize,
+    in_flight: usize,
+    max_in_flight: usize,
+    /// Count of the running spool replays
+    replaying: usize,
+    /// Events, which are evicted from the spool while they are queued or sent,
+    /// they are lost when their sending fails
+    evicted: HashSet<Uuid>,
+    /// Count of the events, which are lost since the drain is started
+    lost: usize,
+    flushed: Vec<Sender<()>>,
 }
 
-impl<'a> SentryProcessorActor {
-    pub fn new<T>(secret_link: T, prepare_json_threads: usize) -> Addr<SentryProcessorActor>
-    where
-        T: Into<Cow<'a, str>>,
-    {
-        let (protocol, pub_key, host, project) = scan_fmt!(
-            &secret_link.into(),
-            "{}://{}@{}/{}",
-            String,
-            String,
-            String,
-            i32
-        )
-        .unwrap();
+impl SentryProcessorActor {
+    pub fn new(
+        router: SentryRouter,
+        prepare_json_threads: usize,
+        endpoint: SentryEndpoint,
+        retry_policy: RetryPolicy,
+        spool: Option<Spool>,
+        queue_policy: QueuePolicy,
+        mapping: EventMapping,
+    ) -> Addr<SentryProcessorActor> {
+        let transports = router
+            .dsns()
+            .iter()
+            .map(|dsn| SentryTransport::new(dsn.clone(), retry_policy))
+            .collect();
 
         SentryProcessorActor::create(|_| SentryProcessorActor {
-            dsn: Dsn {
-                protocol,
-                pub_key,
-                host,
-                project,
-            },
-            client: Client::new(),
-            prepare_actor: Arc::new(PrepareActor::new(prepare_json_threads)),
+            router,
+            transports,
+            endpoint,
+            prepare_actor: Arc::new(PrepareActor::new(prepare_json_threads, mapping)),
+            spool: spool.map(|spool| Arc::new(SpoolActor::new(spool))),
+            delivering: Arc::new(Mutex::new(HashSet::new())),
+            queue: SendQueue::new(queue_policy.capacity, queue_policy.overflow),
+            preparing: 0,
+            in_flight: 0,
+            max_in_flight: queue_policy.max_in_flight.max(1),
+            replaying: 0,
+            evicted: HashSet::new(),
+            lost: 0,
+            flushed: Vec::new(),
         })
     }
-}
 
-impl Actor for SentryProcessorActor {
-    type Context = Context<Self>;
-}
+    /// Start sending of the queued events, while in-flight limit is not reached
+    fn dispatch(&mut self, ctx: &mut Context<Self>) {
+        while self.in_flight < self.max_in_flight {
+            let (target, record) = match self.queue.pop() {
+                Some(job) => job,
+                None => return,
+            };
+            self.in_flight += 1;
 
-impl Handler<GelfProcessorMessage> for SentryProcessorActor {
-    type Result = Option<SentryEvent>;
+            ctx.spawn(
+                process(
+                    record,
+                    self.transports[target].clone(),
+                    self.endpoint,
+                    self.spool.clone(),
+                    Arc::clone(&self.delivering),
+                )
+                .into_actor(self)
+                .map(|(event_id, delivery), act, ctx| {
+                    act.in_flight -= 1;
+                    act.sent(event_id, delivery);
+                    act.dispatch(ctx);
+                    act.notify_if_flushed();
+                }),
+            );
+        }
+    }
+
+    /// Place prepared event into the send queue.
+    /// Returns receiver of the blocked sender, when the queue is full with block overflow policy.
+    fn enqueue(
+        &mut self,
+        target: usize,
+        record: SpoolRecord,
+        ctx: &mut Context<Self>,
+    ) -> Option<Receiver<()>> {
+        match self.queue.push((target, record)) {
+            Enqueued::Queued => None,
+            Enqueued::Dropped((_, dropped)) => {
+                EVENTS_DROPPED.with_label_values(&["queue_overflow"]).inc();
+                warn!(
+                    "sentry queue is full, event is dropped ({} dropped in total)",
+                    self.queue.dropped()
+                );
+                self.lost += 1;
+                self.discard(dropped, ctx);
+                None
+            }
+            Enqueued::Blocked(receiver) => Some(receiver),
+        }
+    }
+
+    /// Remove dropped event from the spool, so it is not replayed
+    fn discard(&mut self, record: SpoolRecord, ctx: &mut Context<Self>) {
+        let spool = match &self.spool {
+            Some(spool) => Arc::clone(spool),
+            None => return,
+        };
+        let delivering = Arc::clone(&self.delivering);
+        ctx.spawn(
+            async move {
+                ack(&spool, &record).await;
+                delivering.lock().unwrap().remove(&record.event_id);
+            }
+            .into_actor(self),
+        );
+    }
 
-    fn handle(&mut self, msg: GelfProcessorMessage, ctx: &mut Self::Context) -> Self::Result {
-        let url = self.dsn.prepare_url();
+    /// Failed event is kept in the spool for replay, without spool it is lost.
+    /// Rejected event is lost always.
+    fn sent(&mut self, event_id: Uuid, delivery: Delivery) {
+        let evicted = self.evicted.remove(&event_id);
+        match delivery {
+            Delivery::Delivered => {}
+            Delivery::Failed if self.spool.is_some() && !evicted => {}
+            Delivery::Failed | Delivery::Rejected => self.lost += 1,
+        }
+    }
 
-        let prepare_actor = Arc::clone(&self.prepare_actor);
+    /// Events, which are evicted from the spool, are lost unless they are queued or sent now
+    fn spool_evicted(&mut self, evicted: Vec<Uuid>) {
+        let delivering = self.delivering.lock().unwrap();
+        for event_id in evicted {
+            if delivering.contains(&event_id) {
+                self.evicted.insert(event_id);
+            } else {
+                self.lost += 1;
+            }
+        }
+    }
 
-        let rb = self.client.post(url.as_str());
+    /// Resolve flush requests, when all queued events and spool replays are processed
+    fn notify_if_flushed(&mut self) {
+        if self.preparing == 0
+            && self.in_flight == 0
+            && self.replaying == 0
+            && self.queue.is_empty()
+        {
+            self.flushed.drain(..).for_each(|flushed| {
+                let _ = flushed.send(());
+            });
+        }
+    }
+
+    /// Resend spooled events, which are not delivered yet
+    fn replay(&mut self, ctx: &mut Context<Self>) {
+        let spool = match &self.spool {
+            Some(spool) => Arc::clone(spool),
+            None => return,
+        };
+        let transports = self.transports.clone();
+        let endpoint = self.endpoint;
+        let delivering = Arc::clone(&self.delivering);
 
+        self.replaying += 1;
         ctx.spawn(
             async move {
-                let sended_request = prepare_actor.send(msg).await;
-                let request = match sended_request {
-                    Ok(r) => r,
-                    Err(e) => {
-                        eprintln!("mailing prepare request error: {:?}", e);
-                        return;
-                    }
-                };
+                let mut results = Vec::new();
+                let mut discarded = 0;
+                let mut cursor = SpoolCursor::default();
+                loop {
+                    let read = SpoolRead {
+                        from: cursor,
+                        limit: SPOOL_REPLAY_BATCH,
+                    };
+                    let records = match spool.send(read).await {
+                        Ok(Ok((records, _))) if records.is_empty() => break,
+                        Ok(Ok((records, next))) => {
+                            cursor = next;
+                            records
+                        }
+                        Ok(Err(e)) => {
+                            error!("sentry spool reading error: {:?}", e);
+                            break;
+                        }
+                        Err(e) => {
+                            error!("mailing spool request error: {:?}", e);
+                            break;
+                        }
+                    };
 
-                match rb.json(&request).send().await {
-                    Ok(r) => {
-                        println!("sentry response: {}", r.text().await.unwrap());
-                        return;
-                    }
-                    Err(e) => {
-                        eprintln!("request sending for sentry error: {:?}", e);
-                        return;
+                    for record in records {
+                        if !delivering.lock().unwrap().insert(record.event_id) {
+                            continue;
+                        }
+
+                        match transports
+                            .iter()
+                            .find(|t| t.dsn().to_string() == record.dsn)
+                        {
+                            Some(transport) => {
+                                let delivery =
+                                    deliver(transport, endpoint, &record, Some(&spool)).await;
+                                results.push((record.event_id, delivery));
+                            }
+                            None => {
+                                warn!(
+                                    "sentry dsn of spooled event is not configured, it is dropped"
+                                );
+                                ack(&spool, &record).await;
+                                discarded += 1;
+                            }
+                        }
+
+                        delivering.lock().unwrap().remove(&record.event_id);
                     }
                 }
+                (results, discarded)
             }
-            .into_actor(self),
+            .into_actor(self)
+            .map(|(results, discarded), act, _| {
+                act.replaying -= 1;
+                act.lost += discarded;
+                results
+                    .into_iter()
+                    .for_each(|(event_id, delivery)| act.sent(event_id, delivery));
+                act.notify_if_flushed();
+            }),
         );
-        None
     }
 }
 
-struct Dsn {
-    protocol: String,
-    pub_key: String,
-    host: String,
-    project: i32,
+impl Actor for SentryProcessorActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if self.spool.is_some() {
+            self.replay(ctx);
+            ctx.run_interval(SPOOL_REPLAY_INTERVAL, |act, ctx| act.replay(ctx));
+        }
+    }
 }
 
-impl Dsn {
-    fn prepare_url(&self) -> String {
-        format!(
-            "{}://{}/api/{}/store/?sentry_version=7&sentry_client=gtsa&sentry_key={}&sentry_timestamp={}",
-            self.protocol,
-            self.host,
-            self.project,
-            self.pub_key,
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs()
-        )
+impl Handler<GelfProcessorMessage> for SentryProcessorActor {
+    type Result = QueuedResponse;
+
+    fn handle(&mut self, msg: GelfProcessorMessage, _ctx: &mut Self::Context) -> Self::Result {
+        let target = match self.router.route(&msg.0) {
+            Some(target) => target,
+            None => {
+                warn!("sentry dsn is not found for gelf message: {}", msg.0);
+                EVENTS_DROPPED.with_label_values(&["no_route"]).inc();
+                self.lost += 1;
+                return QueuedResponse(Box::new(wrap_future(ready(()))));
+            }
+        };
+
+        self.preparing += 1;
+        let prepared = prepare(
+            msg,
+            self.transports[target].dsn().to_string(),
+            Arc::clone(&self.prepare_actor),
+            self.spool.clone(),
+            Arc::clone(&self.delivering),
+        );
+        QueuedResponse(Box::new(wrap_future(prepared).then(
+            move |prepared, act: &mut Self, ctx| {
+                act.preparing -= 1;
+                let blocked = prepared.and_then(|(record, evicted)| {
+                    act.spool_evicted(evicted);
+                    act.enqueue(target, record, ctx)
+                });
+                act.dispatch(ctx);
+                act.notify_if_flushed();
+                wrap_future(async move {
+                    if let Some(blocked) = blocked {
+                        let _ = blocked.await;
+                    }
+                })
+            },
+        )))
     }
 }
 
-struct PrepareActor;
-impl PrepareActor {
-    fn new(threads: usize) -> Addr<PrepareActor> {
-        SyncArbiter::start(threads, || PrepareActor)
+/// Response of the sentry processor.
+/// It is sent when the message is prepared, spooled and placed into the send queue,
+/// so senders wait while the queue is full with block overflow policy.
+pub struct QueuedResponse(ResponseActFuture<SentryProcessorActor, ()>);
+
+impl MessageResponse<SentryProcessorActor, GelfProcessorMessage> for QueuedResponse {
+    fn handle<R: ResponseChannel<GelfProcessorMessage>>(
+        self,
+        ctx: &mut Context<SentryProcessorActor>,
+        tx: Option<R>,
+    ) {
+        ctx.spawn(self.0.map(move |_, _, _| {
+            if let Some(tx) = tx {
+                tx.send(None);
+            }
+        }));
     }
 }
-impl Actor for PrepareActor {
-    type Context = SyncContext<Self>;
+
+/// Message, which is sent when the graceful shutdown is started.
+/// Events lost before it are not reported by the flush.
+pub struct Drain;
+
+impl Message for Drain {
+    type Result = ();
 }
 
-impl Handler<GelfProcessorMessage> for PrepareActor {
-    type Result = Option<SentryEvent>;
+impl Handler<Drain> for SentryProcessorActor {
+    type Result = ();
 
-    fn handle(
-        &mut self,
-        GelfProcessorMessage(msg): GelfProcessorMessage,
-        _ctx: &mut Self::Context,
-    ) -> Self::Result {
-        Some(SentryEvent::from(msg))
+    fn handle(&mut self, _msg: Drain, _ctx: &mut Self::Context) {
+        self.lost = 0;
     }
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-enum SentryLevels {
-    Fatal,
-    Error,
-    Warning,
-    Info,
-    Debug,
+/// Message, which is resolved when all queued events are processed.
+/// Result is the count of events, which are waiting for replay in the spool,
+/// and events, which are lost since the drain is started.
+pub struct Flush;
+
+impl Message for Flush {
+    type Result = Result<usize, Canceled>;
 }
 
-impl From<GelfLevel> for SentryLevels {
-    fn from(level: GelfLevel) -> Self {
-        match level {
-            GelfLevel::Emergency => SentryLevels::Fatal,
-            GelfLevel::Alert => SentryLevels::Error,
-            GelfLevel::Critical => SentryLevels::Error,
-            GelfLevel::Error => SentryLevels::Error,
-            GelfLevel::Warning => SentryLevels::Warning,
-            GelfLevel::Notice => SentryLevels::Warning,
-            GelfLevel::Informational => SentryLevels::Info,
-            GelfLevel::Debug => SentryLevels::Debug,
+impl Handler<Flush> for SentryProcessorActor {
+    type Result = ResponseActFuture<Self, Result<usize, Canceled>>;
+
+    fn handle(&mut self, _msg: Flush, _ctx: &mut Self::Context) -> Self::Result {
+        let (flushed, on_flushed) = channel();
+        self.flushed.push(flushed);
+        self.notify_if_flushed();
+
+        let spool = self.spool.clone();
+        Box::new(
+            wrap_future(async move {
+                on_flushed.await?;
+                let pending = match spool {
+                    Some(spool) => spool.send(SpoolLen).await.unwrap_or_else(|e| {
+                        error!("mailing spool request error: {:?}", e);
+                        0
+                    }),
+                    None => 0,
+                };
+                Ok(pending)
+            })
+            .map(|pending: Result<usize, Canceled>, act: &mut Self, _| {
+                pending.map(|pending| pending + act.lost)
+            }),
+        )
+    }
+}
+
+impl Handler<Ping> for SentryProcessorActor {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Ping, _ctx: &mut Self::Context) {}
+}
+
+/// Prepare gelf message and persist it in the spool before it is placed into the send queue,
+/// so queued events survive restart too. Returns the record and ids of the evicted records.
+async fn prepare(
+    msg: GelfProcessorMessage,
+    dsn: String,
+    prepare_actor: Arc<Addr<PrepareActor>>,
+    spool: Option<Arc<Addr<SpoolActor>>>,
+    delivering: Arc<Mutex<HashSet<Uuid>>>,
+) -> Option<(SpoolRecord, Vec<Uuid>)> {
+    let sended_request = prepare_actor.send(msg).await;
+    let request = match sended_request {
+        Ok(Some(r)) => r,
+        Ok(None) => return None,
+        Err(e) => {
+            error!("mailing prepare request error: {:?}", e);
+            return None;
         }
+    };
+
+    let record = match serde_json::to_vec(&request) {
+        Ok(payload) => SpoolRecord {
+            event_id: request.event_id,
+            dsn,
+            payload,
+        },
+        Err(e) => {
+            error!("sentry event encoding error: {:?}", e);
+            return None;
+        }
+    };
+
+    let mut evicted = Vec::new();
+    if let Some(spool) = &spool {
+        delivering.lock().unwrap().insert(record.event_id);
+        match spool.send(SpoolPush(record.clone())).await {
+            Ok(Ok(ids)) => evicted = ids,
+            Ok(Err(e)) => error!("sentry spool writing error: {:?}", e),
+            Err(e) => error!("mailing spool request error: {:?}", e),
+        }
+    }
+    if !evicted.is_empty() {
+        EVENTS_DROPPED
+            .with_label_values(&["spool_eviction"])
+            .inc_by(evicted.len() as i64);
+        warn!(
+            "sentry spool is full, {} oldest events are evicted",
+            evicted.len()
+        )
     }
+    Some((record, evicted))
 }
 
-#[derive(Serialize, Deserialize)]
-struct SentryExceptionValueMechanism {
-    r#type: String,
-    data: Map<String, Value>,
+/// Result of the event sending
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Delivery {
+    Delivered,
+    /// Event could be sent later
+    Failed,
+    /// Event could not be sent at all, it is removed from the spool
+    Rejected,
 }
 
-#[derive(Serialize, Deserialize)]
-struct SentryExceptionValue {
-    r#type: String,
-    value: Value,
-    mechanism: Option<SentryExceptionValueMechanism>,
+/// Send queued event to the sentry, returns event id and its delivery
+async fn process(
+    record: SpoolRecord,
+    transport: SentryTransport,
+    endpoint: SentryEndpoint,
+    spool: Option<Arc<Addr<SpoolActor>>>,
+    delivering: Arc<Mutex<HashSet<Uuid>>>,
+) -> (Uuid, Delivery) {
+    let delivery = deliver(&transport, endpoint, &record, spool.as_deref()).await;
+    delivering.lock().unwrap().remove(&record.event_id);
+    (record.event_id, delivery)
 }
 
-#[derive(Serialize, Deserialize)]
-struct SentryException {
-    values: Vec<SentryExceptionValue>,
+/// Send spooled record to the sentry and acknowledge it after delivery.
+/// Rejected record is acknowledged too, so it is not replayed.
+async fn deliver(
+    transport: &SentryTransport,
+    endpoint: SentryEndpoint,
+    record: &SpoolRecord,
+    spool: Option<&Addr<SpoolActor>>,
+) -> Delivery {
+    let delivery = match endpoint.prepare_body(record) {
+        Ok(body) => match transport.send(endpoint, body).await {
+            Ok(()) => Delivery::Delivered,
+            Err(SendError::Failed(e)) => {
+                error!("{}", e);
+                return Delivery::Failed;
+            }
+            Err(e) => {
+                error!("{}", e);
+                Delivery::Rejected
+            }
+        },
+        Err(e) => {
+            error!("sentry event encoding error: {:?}", e);
+            Delivery::Rejected
+        }
+    };
+
+    if delivery == Delivery::Rejected {
+        EVENTS_DROPPED.with_label_values(&["sentry_rejected"]).inc();
+    }
+    if let Some(spool) = spool {
+        ack(spool, record).await;
+    }
+    delivery
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct SentryEvent {
-    event_id: Uuid,
-    server_name: String,
-    timestamp: f64,
-    level: SentryLevels,
-    exception: SentryException,
+async fn ack(spool: &Addr<SpoolActor>, record: &SpoolRecord) {
+    match spool.send(SpoolAck(record.event_id)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => error!("sentry spool writing error: {:?}", e),
+        Err(e) => error!("mailing spool request error: {:?}", e),
+    }
 }
 
-impl From<GelfDataWrapper> for SentryEvent {
-    fn from(gd: GelfDataWrapper) -> Self {
-        let GelfData {
-            host,
-            level,
-            short_message,
-            timestamp,
-            meta,
-            mechanism_data,
-            ..
-        } = gd.into_gelf();
-        let mut exception = SentryException {
-            values: meta
-                .into_iter()
-                .map(|(k, v)| SentryExceptionValue {
-                    r#type: k,
-                    value: v,
-                    mechanism: None,
-                })
-                .collect::<Vec<SentryExceptionValue>>(),
-        };
+/// Sentry api endpoint, which receives events
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SentryEndpoint {
+    /// `/api/{project}/envelope/` endpoint
+    Envelope,
+    /// Deprecated `/api/{project}/store/` endpoint, which is used by old self-hosted sentry
+    Store,
+}
 
-        exception.values.push(SentryExceptionValue {
-            r#type: String::from("GelfException"),
-            value: Value::String(short_message),
-            mechanism: Some(SentryExceptionValueMechanism {
-                r#type: String::from("generic"),
-                data: mechanism_data,
-            }),
-        });
-
-        SentryEvent {
-            event_id: uuid::Uuid::new_v4(),
-            server_name: host,
-            timestamp,
-            level: SentryLevels::from(level),
-            exception,
+impl SentryEndpoint {
+    pub fn path(self) -> &'static str {
+        match self {
+            SentryEndpoint::Envelope => "envelope",
+            SentryEndpoint::Store => "store",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            SentryEndpoint::Envelope => ENVELOPE_CONTENT_TYPE,
+            SentryEndpoint::Store => "application/json",
+        }
+    }
+
+    fn prepare_body(self, record: &SpoolRecord) -> serde_json::Result<Vec<u8>> {
+        match self {
+            SentryEndpoint::Envelope => {
+                new_event_envelope(&record.event_id, &record.dsn, &record.payload)
+            }
+            SentryEndpoint::Store => Ok(record.payload.clone()),
         }
     }
 }
 
+struct PrepareActor {
+    mapping: Arc<EventMapping>,
+}
+impl PrepareActor {
+    fn new(threads: usize, mapping: EventMapping) -> Addr<PrepareActor> {
+        let mapping = Arc::new(mapping);
+        SyncArbiter::start(threads, move || PrepareActor {
+            mapping: Arc::clone(&mapping),
+        })
+    }
+}
+impl Actor for PrepareActor {
+    type Context = SyncContext<Self>;
+}
+
+impl Handler<GelfProcessorMessage> for PrepareActor {
+    type Result = Option<SentryEvent>;
+
+    fn handle(
+        &mut self,
+        GelfProcessorMessage(msg): GelfProcessorMessage,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        Some(SentryEvent::new(msg, &self.mapping))
+    }
+}
+
 #[cfg(test)]
 mod unpacker {
     use super::*;
+    use crate::gelf::gelf_reader::{GelfDataWrapper, ParseMode};
+    use crate::sentry::dsn::Dsn;
+    use crate::sentry::queue::OverflowPolicy;
+    use std::env::temp_dir;
 
-    #[test]
-    fn test_conert() {
-        let s = SentryEvent::from(
+    fn gelf() -> GelfProcessorMessage {
+        GelfProcessorMessage(
             GelfDataWrapper::from_slice(
                 br#"{
-                        "version":"1.1",
-                        "host":"example.org",
-                        "short_message":"A short message",
-                        "level":5,
-                        "_some_info":"foo",
-                        "timestamp":1582213226
-                    }"#,
+                    "version":"1.1",
+                    "host":"example.org",
+                    "short_message":"A short message",
+                    "level":5,
+                    "_some_info":"foo",
+                    "timestamp":1582213226
+                }"#,
+                ParseMode::Strict,
             )
             .unwrap(),
-        );
+        )
+    }
+
+    #[test]
+    fn test_endpoint() {
+        let dsn: Dsn = "https://key@example.org/42".parse().unwrap();
 
-        assert!(matches!(s.level, SentryLevels::Warning));
-        assert_eq!(s.server_name, "example.org");
-        assert_eq!(
-            s.exception.values.last().unwrap().value.as_str().unwrap(),
-            "A short message"
-        );
         assert_eq!(
-            s.exception
-                .values
-                .last()
-                .unwrap()
-                .mechanism
-                .as_ref()
-                .unwrap()
-                .r#type,
-            "generic"
+            dsn.api_url(SentryEndpoint::Envelope.path()),
+            "https://example.org/api/42/envelope/"
         );
         assert_eq!(
-            s.exception.values.first().unwrap().value.as_str().unwrap(),
-            "foo"
+            dsn.api_url(SentryEndpoint::Store.path()),
+            "https://example.org/api/42/store/"
         );
-        assert_eq!(s.exception.values.first().unwrap().r#type, "some_info");
     }
 
     #[actix_rt::test]
     async fn test_actor() {
-        let sentry_prepare = PrepareActor::new(1);
-
-        let s = sentry_prepare
-            .send(GelfProcessorMessage(
-                GelfDataWrapper::from_slice(
-                    br#"{
-                        "version":"1.1",
-                        "host":"example.org",
-                        "short_message":"A short message",
-                        "level":5,
-                        "_some_info":"foo",
-                        "timestamp":1582213226
-                    }"#,
-                )
-                .unwrap(),
-            ))
-            .await
-            .unwrap()
-            .unwrap();
+        let sentry_prepare = PrepareActor::new(1, EventMapping::default());
+
+        let s = sentry_prepare.send(gelf()).await.unwrap().unwrap();
 
-        assert!(matches!(s.level, SentryLevels::Warning));
         assert_eq!(s.server_name, "example.org");
-        assert_eq!(
-            s.exception.values.last().unwrap().value.as_str().unwrap(),
-            "A short message"
-        );
-        assert_eq!(
-            s.exception
-                .values
-                .last()
-                .unwrap()
-                .mechanism
-                .as_ref()
-                .unwrap()
-                .r#type,
-            "generic"
-        );
-        assert_eq!(
-            s.exception.values.first().unwrap().value.as_str().unwrap(),
-            "foo"
+        assert_eq!(s.logentry.formatted, "A short message");
+        assert_eq!(s.extra["some_info"], "foo");
+        assert!(s.exception.is_none());
+    }
+
+    #[actix_rt::test]
+    async fn test_spooled_before_queue() {
+        let dir = temp_dir().join(format!("gtsa-prepare-{}", Uuid::new_v4()));
+        let spool = Arc::new(SpoolActor::new(Spool::open(&dir, 1024 * 1024).unwrap()));
+        let delivering = Arc::new(Mutex::new(HashSet::new()));
+
+        let (record, _) = prepare(
+            gelf(),
+            "https://key@example.org/1".to_string(),
+            Arc::new(PrepareActor::new(1, EventMapping::default())),
+            Some(Arc::clone(&spool)),
+            Arc::clone(&delivering),
+        )
+        .await
+        .unwrap();
+
+        // queued event is in the spool, but it is not replayed while it waits for sending
+        let read = || SpoolRead {
+            from: SpoolCursor::default(),
+            limit: SPOOL_REPLAY_BATCH,
+        };
+        let (pending, _) = spool.send(read()).await.unwrap().unwrap();
+        assert_eq!(pending, vec![record.clone()]);
+        assert!(delivering.lock().unwrap().contains(&record.event_id));
+
+        ack(&spool, &record).await;
+        assert!(spool.send(read()).await.unwrap().unwrap().0.is_empty());
+    }
+
+    fn processor(dsn: &str, spool: Option<Spool>) -> Addr<SentryProcessorActor> {
+        SentryProcessorActor::new(
+            SentryRouter::new(Vec::new(), Some(dsn.parse().unwrap())),
+            1,
+            SentryEndpoint::Store,
+            RetryPolicy {
+                max_retries: 0,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(1),
+            },
+            spool,
+            QueuePolicy {
+                capacity: 10,
+                max_in_flight: 1,
+                overflow: OverflowPolicy::DropNewest,
+            },
+            EventMapping::default(),
+        )
+    }
+
+    #[actix_rt::test]
+    async fn test_flush_reports_undelivered() {
+        // nothing listens on the discard port, so the delivery fails
+        let processor = processor("http://key@127.0.0.1:9/1", None);
+        assert_eq!(processor.send(Flush).await.unwrap(), Ok(0));
+
+        processor.send(gelf()).await.unwrap();
+        assert_eq!(processor.send(Flush).await.unwrap(), Ok(1));
+
+        // events lost before the drain are not reported
+        processor.send(Drain).await.unwrap();
+        assert_eq!(processor.send(Flush).await.unwrap(), Ok(0));
+    }
+
+    #[actix_rt::test]
+    async fn test_flush_reports_spooled() {
+        let dir = temp_dir().join(format!("gtsa-flush-{}", Uuid::new_v4()));
+        let processor = processor(
+            "http://key@127.0.0.1:9/1",
+            Some(Spool::open(&dir, 1024 * 1024).unwrap()),
         );
-        assert_eq!(s.exception.values.first().unwrap().r#type, "some_info");
+        processor.send(Drain).await.unwrap();
+
+        // failed event waits for replay in the spool, it is not counted twice
+        processor.send(gelf()).await.unwrap();
+        assert_eq!(processor.send(Flush).await.unwrap(), Ok(1));
+        assert_eq!(processor.send(Flush).await.unwrap(), Ok(1));
     }
 }
@@ -0,0 +1,530 @@
+use actix::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+const SEGMENT_EXTENSION: &str = "seg";
+const ACK_EXTENSION: &str = "ack";
+/// Every spool is splitted to this count of segments at least
+const SEGMENTS_PER_SPOOL: u64 = 8;
+
+/// Prepared sentry event, which is persisted before sending
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpoolRecord {
+    pub event_id: Uuid,
+    pub dsn: String,
+    pub payload: Vec<u8>,
+}
+
+impl SpoolRecord {
+    fn encode(&self) -> Vec<u8> {
+        let len = 16 + 2 + self.dsn.len() + self.payload.len();
+        let mut buf = Vec::with_capacity(4 + len);
+        buf.extend_from_slice(&(len as u32).to_le_bytes());
+        buf.extend_from_slice(self.event_id.as_bytes());
+        buf.extend_from_slice(&(self.dsn.len() as u16).to_le_bytes());
+        buf.extend_from_slice(self.dsn.as_bytes());
+        buf.extend_from_slice(&self.payload);
+        buf
+    }
+
+    /// Decode record without the length prefix
+    fn decode(record: &[u8]) -> Option<SpoolRecord> {
+        if record.len() < 18 {
+            return None;
+        }
+        let dsn_len = u16::from_le_bytes(record[16..18].try_into().unwrap()) as usize;
+        if record.len() < 18 + dsn_len {
+            return None;
+        }
+        Some(SpoolRecord {
+            event_id: Uuid::from_slice(&record[..16]).unwrap(),
+            dsn: String::from_utf8_lossy(&record[18..18 + dsn_len]).into_owned(),
+            payload: record[18 + dsn_len..].to_vec(),
+        })
+    }
+
+    /// Decode records of the segment with their offsets, incomplete record at the end is skipped
+    fn decode_all(buf: &[u8]) -> Vec<(u64, SpoolRecord)> {
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while buf.len() >= offset + 4 {
+            let len = u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap()) as usize;
+            if buf.len() < offset + 4 + len {
+                break;
+            }
+            match SpoolRecord::decode(&buf[offset + 4..offset + 4 + len]) {
+                Some(record) => records.push((offset as u64, record)),
+                None => break,
+            }
+            offset += 4 + len;
+        }
+        records
+    }
+}
+
+/// Position in the spool, replay reads records after it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SpoolCursor {
+    segment: u64,
+    offset: u64,
+}
+
+struct Segment {
+    id: u64,
+    /// Size of the segment and ack files
+    bytes: u64,
+    /// End of the segment file, where the next record is appended
+    end: u64,
+    /// Offsets of the not acknowledged records
+    pending: BTreeMap<u64, Uuid>,
+}
+
+/// Disk-backed queue of the sentry events.
+/// Events are appended to the segment files and acknowledged in the ack files,
+/// segments are removed when all of their events are acknowledged.
+/// Oldest segments are evicted when spool is bigger than `max_bytes`.
+/// Offsets of the pending events are kept in memory, so replay reads only them.
+pub struct Spool {
+    dir: PathBuf,
+    max_bytes: u64,
+    segment_bytes: u64,
+    segments: VecDeque<Segment>,
+    /// Segment and offset of the pending events
+    index: HashMap<Uuid, SpoolCursor>,
+    active: Option<File>,
+}
+
+impl Spool {
+    pub fn open<P: AsRef<Path>>(dir: P, max_bytes: u64) -> Result<Spool> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut ids: Vec<u64> = fs::read_dir(&dir)?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != SEGMENT_EXTENSION {
+                    return None;
+                }
+                path.file_stem()?.to_str()?.parse().ok()
+            })
+            .collect();
+        ids.sort_unstable();
+
+        let mut spool = Spool {
+            dir,
+            max_bytes,
+            segment_bytes: (max_bytes / SEGMENTS_PER_SPOOL).max(1),
+            segments: VecDeque::with_capacity(ids.len()),
+            index: HashMap::new(),
+            active: None,
+        };
+
+        for id in ids {
+            let records = SpoolRecord::decode_all(&read_file(&spool.path(id, SEGMENT_EXTENSION))?);
+            let acks = read_file(&spool.path(id, ACK_EXTENSION))?;
+            let acks: HashSet<Uuid> = acks
+                .chunks_exact(16)
+                .filter_map(|id| Uuid::from_slice(id).ok())
+                .collect();
+
+            let end = spool.file_size(id, SEGMENT_EXTENSION);
+            let segment = Segment {
+                id,
+                bytes: end + spool.file_size(id, ACK_EXTENSION),
+                end,
+                pending: records
+                    .into_iter()
+                    .filter(|(_, r)| !acks.contains(&r.event_id))
+                    .map(|(offset, r)| (offset, r.event_id))
+                    .collect(),
+            };
+
+            if segment.pending.is_empty() {
+                spool.remove_files(id)?;
+            } else {
+                for (offset, event_id) in &segment.pending {
+                    spool.index.insert(
+                        *event_id,
+                        SpoolCursor {
+                            segment: id,
+                            offset: *offset,
+                        },
+                    );
+                }
+                spool.segments.push_back(segment);
+            }
+        }
+
+        Ok(spool)
+    }
+
+    /// Persist record, returns ids of the evicted records
+    pub fn push(&mut self, record: &SpoolRecord) -> Result<Vec<Uuid>> {
+        let full = self
+            .segments
+            .back()
+            .is_none_or(|s| s.bytes >= self.segment_bytes);
+        if self.active.is_none() || full {
+            let id = self.segments.back().map_or(0, |s| s.id + 1);
+            self.active = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(self.path(id, SEGMENT_EXTENSION))?,
+            );
+            self.segments.push_back(Segment {
+                id,
+                bytes: 0,
+                end: 0,
+                pending: BTreeMap::new(),
+            });
+        }
+
+        let buf = record.encode();
+        self.active.as_mut().unwrap().write_all(&buf)?;
+        let segment = self.segments.back_mut().unwrap();
+        let position = SpoolCursor {
+            segment: segment.id,
+            offset: segment.end,
+        };
+        segment.bytes += buf.len() as u64;
+        segment.end += buf.len() as u64;
+        segment.pending.insert(position.offset, record.event_id);
+        self.index.insert(record.event_id, position);
+
+        self.evict()
+    }
+
+    /// Acknowledge delivered record
+    pub fn ack(&mut self, event_id: &Uuid) -> Result<()> {
+        let position = match self.index.remove(event_id) {
+            Some(position) => position,
+            None => return Ok(()),
+        };
+        let index = match self.segments.iter().position(|s| s.id == position.segment) {
+            Some(index) => index,
+            None => return Ok(()),
+        };
+
+        let segment = &mut self.segments[index];
+        segment.pending.remove(&position.offset);
+        let id = segment.id;
+
+        if segment.pending.is_empty() {
+            if index == self.segments.len() - 1 {
+                self.active = None;
+            }
+            self.segments.remove(index);
+            return self.remove_files(id);
+        }
+
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(id, ACK_EXTENSION))?
+            .write_all(event_id.as_bytes())?;
+        self.segments[index].bytes += 16;
+        Ok(())
+    }
+
+    /// Returns up to `limit` not acknowledged records from the cursor, oldest first,
+    /// and the cursor of the next read. Only pending records are read from the segments.
+    pub fn read(&self, from: SpoolCursor, limit: usize) -> Result<(Vec<SpoolRecord>, SpoolCursor)> {
+        let mut records = Vec::new();
+        let mut next = from;
+        for segment in self.segments.iter().filter(|s| s.id >= from.segment) {
+            let start = if segment.id == from.segment {
+                from.offset
+            } else {
+                0
+            };
+            let mut file = None;
+            for (offset, _) in segment.pending.range(start..) {
+                if records.len() >= limit {
+                    return Ok((records, next));
+                }
+                if file.is_none() {
+                    file = Some(File::open(self.path(segment.id, SEGMENT_EXTENSION))?);
+                }
+                records.extend(read_record(file.as_mut().unwrap(), *offset)?);
+                next = SpoolCursor {
+                    segment: segment.id,
+                    offset: offset + 1,
+                };
+            }
+        }
+        Ok((records, next))
+    }
+
+    /// Count of not acknowledged records
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn evict(&mut self) -> Result<Vec<Uuid>> {
+        let mut evicted = Vec::new();
+        while self.segments.len() > 1 && self.bytes() > self.max_bytes {
+            let segment = self.segments.pop_front().unwrap();
+            for event_id in segment.pending.values() {
+                self.index.remove(event_id);
+            }
+            evicted.extend(segment.pending.values());
+            self.remove_files(segment.id)?;
+        }
+        Ok(evicted)
+    }
+
+    fn bytes(&self) -> u64 {
+        self.segments.iter().map(|s| s.bytes).sum()
+    }
+
+    fn path(&self, id: u64, extension: &str) -> PathBuf {
+        self.dir.join(format!("{:020}.{}", id, extension))
+    }
+
+    fn file_size(&self, id: u64, extension: &str) -> u64 {
+        fs::metadata(self.path(id, extension)).map_or(0, |m| m.len())
+    }
+
+    fn remove_files(&self, id: u64) -> Result<()> {
+        for extension in &[SEGMENT_EXTENSION, ACK_EXTENSION] {
+            match fs::remove_file(self.path(id, extension)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read the record at the offset of the segment file
+fn read_record(file: &mut File, offset: u64) -> Result<Option<SpoolRecord>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut len = [0; 4];
+    file.read_exact(&mut len)?;
+    let mut record = vec![0; u32::from_le_bytes(len) as usize];
+    file.read_exact(&mut record)?;
+    Ok(SpoolRecord::decode(&record))
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            file.read_to_end(&mut buf)?;
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    Ok(buf)
+}
+
+/// Message, which persists the record, returns ids of the evicted records
+pub struct SpoolPush(pub SpoolRecord);
+
+impl Message for SpoolPush {
+    type Result = Result<Vec<Uuid>>;
+}
+
+/// Message, which acknowledges delivered record
+pub struct SpoolAck(pub Uuid);
+
+impl Message for SpoolAck {
+    type Result = Result<()>;
+}
+
+/// Message, which returns not acknowledged records after the cursor and the next cursor
+pub struct SpoolRead {
+    pub from: SpoolCursor,
+    pub limit: usize,
+}
+
+impl Message for SpoolRead {
+    type Result = Result<(Vec<SpoolRecord>, SpoolCursor)>;
+}
+
+/// Message, which returns count of the not acknowledged records
+pub struct SpoolLen;
+
+impl Message for SpoolLen {
+    type Result = usize;
+}
+
+/// Actor, which owns the spool and does blocking file operations
+pub struct SpoolActor {
+    spool: Spool,
+}
+
+impl SpoolActor {
+    pub fn new(spool: Spool) -> Addr<SpoolActor> {
+        let spool = Mutex::new(Some(spool));
+        SyncArbiter::start(1, move || SpoolActor {
+            spool: spool
+                .lock()
+                .unwrap()
+                .take()
+                .expect("spool actor must be started once"),
+        })
+    }
+}
+
+impl Actor for SpoolActor {
+    type Context = SyncContext<Self>;
+}
+
+impl Handler<SpoolPush> for SpoolActor {
+    type Result = Result<Vec<Uuid>>;
+
+    fn handle(&mut self, SpoolPush(record): SpoolPush, _ctx: &mut Self::Context) -> Self::Result {
+        if record.dsn.len() > u16::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "dsn is too long"));
+        }
+        self.spool.push(&record)
+    }
+}
+
+impl Handler<SpoolAck> for SpoolActor {
+    type Result = Result<()>;
+
+    fn handle(&mut self, SpoolAck(event_id): SpoolAck, _ctx: &mut Self::Context) -> Self::Result {
+        self.spool.ack(&event_id)
+    }
+}
+
+impl Handler<SpoolRead> for SpoolActor {
+    type Result = Result<(Vec<SpoolRecord>, SpoolCursor)>;
+
+    fn handle(&mut self, msg: SpoolRead, _ctx: &mut Self::Context) -> Self::Result {
+        self.spool.read(msg.from, msg.limit)
+    }
+}
+
+impl Handler<SpoolLen> for SpoolActor {
+    type Result = usize;
+
+    fn handle(&mut self, _msg: SpoolLen, _ctx: &mut Self::Context) -> Self::Result {
+        self.spool.len()
+    }
+}
+
+#[cfg(test)]
+mod segments {
+    use super::*;
+    use std::env::temp_dir;
+
+    fn spool_dir(name: &str) -> PathBuf {
+        let dir = temp_dir().join(format!("gtsa-spool-{}-{}", name, Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn pending(spool: &Spool) -> Vec<SpoolRecord> {
+        spool.read(SpoolCursor::default(), usize::MAX).unwrap().0
+    }
+
+    fn record(payload: &str) -> SpoolRecord {
+        SpoolRecord {
+            event_id: Uuid::new_v4(),
+            dsn: "https://key@example.org/1".to_string(),
+            payload: payload.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_replay_after_restart() {
+        let dir = spool_dir("replay");
+        let (first, second) = (record("first"), record("second"));
+
+        let mut spool = Spool::open(&dir, 1024 * 1024).unwrap();
+        assert!(spool.push(&first).unwrap().is_empty());
+        assert!(spool.push(&second).unwrap().is_empty());
+        spool.ack(&first.event_id).unwrap();
+        drop(spool);
+
+        let mut spool = Spool::open(&dir, 1024 * 1024).unwrap();
+        assert_eq!(pending(&spool), vec![second.clone()]);
+
+        spool.ack(&second.event_id).unwrap();
+        assert!(pending(&spool).is_empty());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_eviction() {
+        let dir = spool_dir("eviction");
+        let records: Vec<SpoolRecord> = (0..16).map(|i| record(&"x".repeat(i + 64))).collect();
+
+        let mut spool = Spool::open(&dir, 512).unwrap();
+        let evicted: Vec<Uuid> = records
+            .iter()
+            .flat_map(|r| spool.push(r).unwrap())
+            .collect();
+        let pending = pending(&spool);
+
+        assert!(!evicted.is_empty());
+        assert!(evicted.contains(&records[0].event_id));
+        assert_eq!(evicted.len() + pending.len(), records.len());
+        assert_eq!(spool.len(), pending.len());
+        assert_eq!(pending.last(), records.last());
+        assert!(spool.bytes() <= 512 || spool.segments.len() == 1);
+    }
+
+    #[test]
+    fn test_read_batches() {
+        let dir = spool_dir("batches");
+        let records: Vec<SpoolRecord> = (0..3).map(|i| record(&i.to_string())).collect();
+
+        let mut spool = Spool::open(&dir, 1024 * 1024).unwrap();
+        records.iter().for_each(|r| {
+            spool.push(r).unwrap();
+        });
+        spool.ack(&records[1].event_id).unwrap();
+
+        let (first, cursor) = spool.read(SpoolCursor::default(), 1).unwrap();
+        assert_eq!(first, vec![records[0].clone()]);
+        let (rest, cursor) = spool.read(cursor, 10).unwrap();
+        assert_eq!(rest, vec![records[2].clone()]);
+        assert!(spool.read(cursor, 10).unwrap().0.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_actor() {
+        let spool_actor = SpoolActor::new(Spool::open(spool_dir("actor"), 1024).unwrap());
+        let r = record("event");
+
+        spool_actor
+            .send(SpoolPush(r.clone()))
+            .await
+            .unwrap()
+            .unwrap();
+        let (pending, _) = spool_actor
+            .send(SpoolRead {
+                from: SpoolCursor::default(),
+                limit: 10,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pending, vec![r.clone()]);
+
+        spool_actor
+            .send(SpoolAck(r.event_id))
+            .await
+            .unwrap()
+            .unwrap();
+        let (pending, _) = spool_actor
+            .send(SpoolRead {
+                from: SpoolCursor::default(),
+                limit: 10,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(pending.is_empty());
+    }
+}
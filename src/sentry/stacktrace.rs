@@ -0,0 +1,452 @@
+use crate::sentry::event::SentryFrame;
+
+/// Module prefixes of the jvm and .net runtimes, their frames are not in app
+const RUNTIME_MODULES: [&str; 9] = [
+    "java.",
+    "javax.",
+    "jdk.",
+    "sun.",
+    "com.sun.",
+    "kotlin.",
+    "scala.",
+    "System.",
+    "Microsoft.",
+];
+/// Paths of the installed packages, their frames are not in app
+const LIBRARY_PATHS: [&str; 5] = [
+    "site-packages",
+    "dist-packages",
+    "/lib/python",
+    "node_modules",
+    "/pkg/mod/",
+];
+
+/// Parser of the stack trace format, it returns exceptions of the stack trace
+type Parser = fn(&str) -> Option<Vec<ParsedException>>;
+/// Parsers are checked in order, the first stack trace with frames is used
+const PARSERS: [Parser; 3] = [parse_python, parse_go, parse_at_frames];
+
+/// Exception of the parsed stack trace.
+/// Frames are ordered from the oldest call to the newest one, as sentry expects.
+#[derive(Debug, PartialEq)]
+pub struct ParsedException {
+    pub r#type: String,
+    pub value: String,
+    pub frames: Vec<SentryFrame>,
+}
+
+/// Parse stack trace of java, .net, node, python or go.
+/// Chained exceptions are ordered from the cause to the outermost one.
+/// Returns none, when the text has no stack frames.
+pub fn parse(text: &str) -> Option<Vec<ParsedException>> {
+    PARSERS
+        .iter()
+        .filter_map(|parser| parser(text))
+        .find(|exceptions| exceptions.iter().any(|e| !e.frames.is_empty()))
+}
+
+/// Java, .net and node stack traces, where every frame starts with `at`.
+/// Frames and causes are printed from the newest to the oldest.
+fn parse_at_frames(text: &str) -> Option<Vec<ParsedException>> {
+    let mut exceptions: Vec<ParsedException> = Vec::new();
+    let mut current = 0;
+    for line in text.lines().map(str::trim) {
+        if let Some(frame) = line.strip_prefix("at ").and_then(parse_at_frame) {
+            if exceptions.is_empty() {
+                exceptions.push(new_exception("Error", ""));
+            }
+            exceptions[current].frames.push(frame);
+        } else if line.starts_with("--- End of inner exception stack trace") {
+            current = current.saturating_sub(1);
+        } else if let Some(header) = line
+            .strip_prefix("Caused by: ")
+            .or_else(|| line.strip_prefix("---> "))
+        {
+            push_headers(&mut exceptions, header);
+            current = exceptions.len() - 1;
+        } else if exceptions.iter().all(|e| e.frames.is_empty()) {
+            // the last line before the frames is the header, log text could precede it
+            let header = match line.strip_prefix("Exception in thread \"") {
+                Some(header) => header.split_once("\" ").map_or(header, |(_, h)| h),
+                None => line,
+            };
+            if split_header(header).is_some() {
+                exceptions.clear();
+                push_headers(&mut exceptions, header);
+                current = exceptions.len() - 1;
+            }
+        }
+    }
+
+    exceptions
+        .iter_mut()
+        .for_each(|exception| exception.frames.reverse());
+    exceptions.reverse();
+    Some(exceptions)
+}
+
+/// Headers of .net inner exceptions are joined with `--->`
+fn push_headers(exceptions: &mut Vec<ParsedException>, line: &str) {
+    for header in line.split(" ---> ") {
+        let (r#type, value) = split_header(header).unwrap_or(("Error", header));
+        exceptions.push(new_exception(r#type, value));
+    }
+}
+
+/// Frame of java `pkg.Class.method(File.java:12)`,
+/// .net `Ns.Class.Method(Args args) in C:\File.cs:line 12`
+/// or node `func (/app/file.js:12:5)`
+fn parse_at_frame(line: &str) -> Option<SentryFrame> {
+    if let Some((call, location)) = line.rsplit_once(") in ") {
+        let (file, lineno) = location.rsplit_once(":line ")?;
+        let mut frame = module_frame(call.split('(').next()?);
+        frame.filename = Some(file.to_string());
+        frame.lineno = lineno.trim().parse().ok();
+        return Some(frame);
+    }
+
+    if let Some((function, location)) = line.split_once(" (") {
+        let mut frame = node_frame(location.strip_suffix(')')?)?;
+        frame.function = Some(function.to_string());
+        return Some(frame);
+    }
+
+    match line.split_once('(') {
+        Some((call, location)) => {
+            let call = call.rsplit('/').next()?;
+            let mut frame = module_frame(call);
+            let location = location.split(')').next()?;
+            if let Some((file, lineno)) = location.rsplit_once(':') {
+                frame.filename = Some(file.to_string());
+                frame.lineno = lineno.parse().ok();
+            }
+            Some(frame)
+        }
+        None => node_frame(line),
+    }
+}
+
+/// Java or .net frame with `pkg.Class` module and `method` function
+fn module_frame(call: &str) -> SentryFrame {
+    let mut frame = SentryFrame::default();
+    match call.rsplit_once('.') {
+        Some((module, function)) => {
+            frame.in_app = Some(!RUNTIME_MODULES.iter().any(|m| module.starts_with(m)));
+            frame.module = Some(module.to_string());
+            frame.function = Some(function.to_string());
+        }
+        None => frame.function = Some(call.to_string()),
+    }
+    frame
+}
+
+/// Node frame location `/app/file.js:12:5`
+fn node_frame(location: &str) -> Option<SentryFrame> {
+    let mut parts = location.rsplitn(3, ':');
+    let (colno, lineno, file) = (parts.next()?, parts.next()?, parts.next()?);
+    Some(SentryFrame {
+        filename: Some(file.to_string()),
+        lineno: Some(lineno.parse().ok()?),
+        colno: Some(colno.parse().ok()?),
+        in_app: Some(
+            !file.starts_with("node:") && !file.starts_with("internal/") && !is_library(file),
+        ),
+        ..SentryFrame::default()
+    })
+}
+
+/// Python traceback, which is printed from the oldest call to the newest one.
+/// Chained exceptions are printed from the cause to the outermost one.
+fn parse_python(text: &str) -> Option<Vec<ParsedException>> {
+    let mut exceptions = Vec::new();
+    let mut frames: Option<Vec<SentryFrame>> = None;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed == "Traceback (most recent call last):" {
+            frames = Some(Vec::new());
+            continue;
+        }
+        let traceback = match frames.as_mut() {
+            Some(traceback) => traceback,
+            None => continue,
+        };
+
+        if let Some(frame) = trimmed.strip_prefix("File \"") {
+            // File "/app/billing.py", line 12, in pay
+            let (file, location) = match frame.split_once("\", line ") {
+                Some(frame) => frame,
+                None => continue,
+            };
+            let (lineno, function) = location.split_once(", in ").unwrap_or((location, ""));
+            traceback.push(SentryFrame {
+                filename: Some(file.to_string()),
+                lineno: lineno.parse().ok(),
+                function: Some(function.to_string()).filter(|f| !f.is_empty()),
+                in_app: Some(!is_library(file)),
+                ..SentryFrame::default()
+            });
+        } else if line.starts_with(char::is_whitespace) {
+            // source line of the frame, python 3.11 adds the line with ^ markers under it
+            let marker = trimmed.chars().all(|c| c == '^' || c == '~');
+            if let Some(frame) = traceback.last_mut().filter(|_| !marker) {
+                frame
+                    .context_line
+                    .get_or_insert_with(|| trimmed.to_string());
+            }
+        } else if !trimmed.is_empty() {
+            let (r#type, value) = split_header(trimmed).unwrap_or(("Error", trimmed));
+            let mut exception = new_exception(r#type, value);
+            exception.frames = frames.take().unwrap_or_default();
+            exceptions.push(exception);
+        }
+    }
+    Some(exceptions).filter(|exceptions| !exceptions.is_empty())
+}
+
+/// Go panic with the stack of the panicked goroutine, which is printed from the newest call
+fn parse_go(text: &str) -> Option<Vec<ParsedException>> {
+    let value = text.lines().find_map(|line| line.strip_prefix("panic: "))?;
+    let mut lines = text
+        .lines()
+        .skip_while(|line| !(line.starts_with("goroutine ") && line.ends_with("]:")))
+        .skip(1)
+        .take_while(|line| !line.trim().is_empty())
+        .peekable();
+
+    let mut exception = new_exception("panic", value);
+    while let Some(call) = lines.next() {
+        // main.pay(0x0) and then its location `\t/app/billing.go:12 +0x1d`
+        let call = call.strip_prefix("created by ").unwrap_or(call);
+        let call = call.split(" in goroutine ").next().unwrap_or(call);
+        let function = match call.ends_with(')') {
+            true => call.rsplit_once('(').map_or(call, |(function, _)| function),
+            false => call,
+        };
+        let mut frame = SentryFrame {
+            function: Some(function.to_string()),
+            ..SentryFrame::default()
+        };
+        if let Some(location) = lines.next_if(|line| line.starts_with('\t')) {
+            let location = location.trim().split(' ').next().unwrap_or_default();
+            if let Some((file, lineno)) = location.rsplit_once(':') {
+                frame.filename = Some(file.to_string());
+                frame.lineno = lineno.parse().ok();
+            }
+        }
+        frame.in_app = Some(
+            !function.starts_with("runtime.") && !frame.filename.as_deref().is_some_and(is_library),
+        );
+        exception.frames.push(frame);
+    }
+    exception.frames.reverse();
+    Some(vec![exception])
+}
+
+/// Split `Type: value` header of the exception, the type must not have whitespaces
+fn split_header(line: &str) -> Option<(&str, &str)> {
+    let (r#type, value) = line.split_once(": ").unwrap_or((line, ""));
+    let r#type = r#type.trim_end_matches(':');
+    match r#type.is_empty() || r#type.contains(char::is_whitespace) {
+        true => None,
+        false => Some((r#type, value.trim())),
+    }
+}
+
+fn new_exception(r#type: &str, value: &str) -> ParsedException {
+    ParsedException {
+        r#type: r#type.to_string(),
+        value: value.to_string(),
+        frames: Vec::new(),
+    }
+}
+
+fn is_library(file: &str) -> bool {
+    LIBRARY_PATHS.iter().any(|path| file.contains(path))
+}
+
+#[cfg(test)]
+mod formats {
+    use super::*;
+
+    fn frame(function: &str, file: &str, lineno: u64) -> (String, String, u64) {
+        (function.to_string(), file.to_string(), lineno)
+    }
+
+    fn frames(exception: &ParsedException) -> Vec<(String, String, u64)> {
+        exception
+            .frames
+            .iter()
+            .map(|f| {
+                frame(
+                    f.function.as_deref().unwrap_or_default(),
+                    f.filename.as_deref().unwrap_or_default(),
+                    f.lineno.unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_java() {
+        let parsed = parse(
+            "Payment failed\n\
+             Exception in thread \"main\" java.lang.IllegalStateException: card expired\n\
+             \tat com.acme.Billing.pay(Billing.java:12)\n\
+             \tat java.base/java.lang.Thread.run(Thread.java:834)\n\
+             Caused by: java.io.IOException: disk is full\n\
+             \tat com.acme.Store.save(Store.java:40)\n\
+             \t... 2 more\n",
+        )
+        .unwrap();
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].r#type, "java.io.IOException");
+        assert_eq!(parsed[0].value, "disk is full");
+        assert_eq!(frames(&parsed[0]), vec![frame("save", "Store.java", 40)]);
+        assert_eq!(parsed[1].r#type, "java.lang.IllegalStateException");
+        assert_eq!(parsed[1].value, "card expired");
+        assert_eq!(
+            frames(&parsed[1]),
+            vec![
+                frame("run", "Thread.java", 834),
+                frame("pay", "Billing.java", 12)
+            ]
+        );
+        assert_eq!(parsed[1].frames[0].in_app, Some(false));
+        assert_eq!(
+            parsed[1].frames[1].module.as_deref(),
+            Some("com.acme.Billing")
+        );
+        assert_eq!(parsed[1].frames[1].in_app, Some(true));
+
+        assert!(parse("just a message: without frames").is_none());
+    }
+
+    #[test]
+    fn test_dotnet_and_node() {
+        let parsed = parse(
+            "System.InvalidOperationException: Payment failed ---> System.IO.IOException: disk is full\n   \
+             at Acme.Store.Save(String path) in C:\\src\\Store.cs:line 40\n   \
+             --- End of inner exception stack trace ---\n   \
+             at Acme.Billing.Pay(Card card) in C:\\src\\Billing.cs:line 12\n   \
+             at System.Threading.Tasks.Task.Execute()\n",
+        )
+        .unwrap();
+
+        assert_eq!(parsed[0].r#type, "System.IO.IOException");
+        assert_eq!(
+            frames(&parsed[0]),
+            vec![frame("Save", "C:\\src\\Store.cs", 40)]
+        );
+        assert_eq!(parsed[1].r#type, "System.InvalidOperationException");
+        assert_eq!(parsed[1].value, "Payment failed");
+        assert_eq!(
+            frames(&parsed[1]),
+            vec![
+                frame("Execute", "", 0),
+                frame("Pay", "C:\\src\\Billing.cs", 12)
+            ]
+        );
+        assert_eq!(parsed[1].frames[0].in_app, Some(false));
+
+        let parsed = parse(
+            "TypeError: Cannot read property 'id' of undefined\n    \
+             at pay (/app/billing.js:12:5)\n    \
+             at /app/node_modules/express/router.js:40:7\n    \
+             at node:internal/main:10:3\n",
+        )
+        .unwrap();
+
+        assert_eq!(parsed[0].r#type, "TypeError");
+        assert_eq!(parsed[0].value, "Cannot read property 'id' of undefined");
+        assert_eq!(
+            frames(&parsed[0]),
+            vec![
+                frame("", "node:internal/main", 10),
+                frame("", "/app/node_modules/express/router.js", 40),
+                frame("pay", "/app/billing.js", 12)
+            ]
+        );
+        assert_eq!(parsed[0].frames[2].colno, Some(5));
+        let in_app: Vec<Option<bool>> = parsed[0].frames.iter().map(|f| f.in_app).collect();
+        assert_eq!(in_app, vec![Some(false), Some(false), Some(true)]);
+    }
+
+    #[test]
+    fn test_python() {
+        let parsed = parse(
+            "Traceback (most recent call last):\n  \
+             File \"/app/store.py\", line 40, in save\n    \
+             fd.write(data)\n\
+             OSError: disk is full\n\
+             \n\
+             During handling of the above exception, another exception occurred:\n\
+             \n\
+             Traceback (most recent call last):\n  \
+             File \"/app/billing.py\", line 12, in pay\n    \
+             charge(card)\n    \
+             ^^^^^^^^^^^^\n  \
+             File \"/usr/lib/python3.8/site-packages/stripe/api.py\", line 7, in charge\n    \
+             raise CardError(\"expired\")\n\
+             stripe.error.CardError: expired\n",
+        )
+        .unwrap();
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].r#type, "OSError");
+        assert_eq!(frames(&parsed[0]), vec![frame("save", "/app/store.py", 40)]);
+        assert_eq!(parsed[1].r#type, "stripe.error.CardError");
+        assert_eq!(parsed[1].value, "expired");
+        assert_eq!(
+            frames(&parsed[1]),
+            vec![
+                frame("pay", "/app/billing.py", 12),
+                frame(
+                    "charge",
+                    "/usr/lib/python3.8/site-packages/stripe/api.py",
+                    7
+                )
+            ]
+        );
+        assert_eq!(
+            parsed[1].frames[0].context_line.as_deref(),
+            Some("charge(card)")
+        );
+        assert_eq!(parsed[1].frames[0].in_app, Some(true));
+        assert_eq!(parsed[1].frames[1].in_app, Some(false));
+    }
+
+    #[test]
+    fn test_go() {
+        let parsed = parse(
+            "panic: runtime error: invalid memory address or nil pointer dereference\n\
+             [signal SIGSEGV: segmentation violation code=0x1 addr=0x0 pc=0x4a1b2c]\n\
+             \n\
+             goroutine 1 [running]:\n\
+             main.(*Billing).pay(0x0)\n\
+             \t/app/billing.go:12 +0x1d\n\
+             main.main()\n\
+             \t/app/main.go:5 +0x25\n\
+             \n\
+             goroutine 7 [chan receive]:\n\
+             main.worker()\n\
+             \t/app/worker.go:3 +0x10\n\
+             exit status 2\n",
+        )
+        .unwrap();
+
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].r#type, "panic");
+        assert_eq!(
+            parsed[0].value,
+            "runtime error: invalid memory address or nil pointer dereference"
+        );
+        assert_eq!(
+            frames(&parsed[0]),
+            vec![
+                frame("main.main", "/app/main.go", 5),
+                frame("main.(*Billing).pay", "/app/billing.go", 12)
+            ]
+        );
+    }
+}
@@ -0,0 +1,393 @@
+use crate::gelf::error::GelfError;
+use crate::health;
+use crate::metrics::{
+    SENTRY_EVENTS_FAILED, SENTRY_EVENTS_RATE_LIMITED, SENTRY_EVENTS_SENT, SENTRY_REQUEST_DURATION,
+};
+use crate::sentry::dsn::Dsn;
+use crate::sentry::sentry_processor::SentryEndpoint;
+use log::{debug, warn};
+use prometheus::IntCounterVec;
+use reqwest::header::{HeaderMap, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::{Client, StatusCode};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time::{delay_for, delay_until, Instant as TokioInstant};
+
+/// Header, which contains sentry authentication data
+const X_SENTRY_AUTH: &str = "x-sentry-auth";
+/// Header, which contains sentry rate limits per category
+const X_SENTRY_RATE_LIMITS: &str = "x-sentry-rate-limits";
+/// Rate limit category of the error events
+const EVENT_CATEGORY: &str = "error";
+/// Pause, which used when sentry answers 429 without advertised time
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+/// Longest pause, which is accepted from sentry
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(3600);
+
+/// Retry settings of the sentry requests.
+/// Transport errors, 408 and 5xx responses are retried with exponential backoff,
+/// other 4xx responses are not retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .checked_mul(2u32.saturating_pow(attempt))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
+
+/// Rate limits, which advertised by sentry
+#[derive(Default, Debug)]
+pub struct RateLimits {
+    all: Option<Instant>,
+    categories: HashMap<String, Instant>,
+}
+
+impl RateLimits {
+    /// Returns time until sending of the category is paused
+    pub fn limited_until(&self, category: &str) -> Option<Instant> {
+        let now = Instant::now();
+        self.all
+            .into_iter()
+            .chain(self.categories.get(category).copied())
+            .filter(|until| *until > now)
+            .max()
+    }
+
+    /// Update rate limits from the sentry response
+    pub fn update(&mut self, status: StatusCode, headers: &HeaderMap) {
+        let now = Instant::now();
+
+        if let Some(rate_limits) = headers
+            .get(X_SENTRY_RATE_LIMITS)
+            .and_then(|h| h.to_str().ok())
+        {
+            self.update_from_rate_limits(now, rate_limits);
+        } else if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = headers
+                .get(RETRY_AFTER)
+                .and_then(|h| h.to_str().ok())
+                .and_then(parse_retry_after)
+                .unwrap_or(DEFAULT_RETRY_AFTER);
+            if let Some(until) = now.checked_add(retry_after) {
+                self.limit(None, until);
+            }
+        }
+
+        // 429 always pauses the events, even when the header limits other categories only
+        if status == StatusCode::TOO_MANY_REQUESTS && self.limited_until(EVENT_CATEGORY).is_none() {
+            if let Some(until) = now.checked_add(DEFAULT_RETRY_AFTER) {
+                self.limit(Some(EVENT_CATEGORY), until);
+            }
+        }
+    }
+
+    /// Parse `X-Sentry-Rate-Limits` header, e.g. `60:error;transaction:key, 2700::organization`.
+    /// Limit without categories is applied to all categories.
+    fn update_from_rate_limits(&mut self, now: Instant, rate_limits: &str) {
+        for limit in rate_limits.split(',') {
+            let mut parts = limit.trim().split(':');
+            let retry_after = match parts
+                .next()
+                .and_then(parse_retry_after)
+                .and_then(|retry_after| now.checked_add(retry_after))
+            {
+                Some(retry_after) => retry_after,
+                None => continue,
+            };
+
+            match parts.next().map(str::trim) {
+                None | Some("") => self.limit(None, retry_after),
+                Some(categories) => categories
+                    .split(';')
+                    .for_each(|category| self.limit(Some(category), retry_after)),
+            }
+        }
+    }
+
+    fn limit(&mut self, category: Option<&str>, until: Instant) {
+        let current = match category {
+            Some(category) => self.categories.entry(category.to_string()).or_insert(until),
+            None => self.all.get_or_insert(until),
+        };
+        if *current < until {
+            *current = until;
+        }
+    }
+}
+
+/// Parse seconds of the pause. Negative and non-finite values are rejected,
+/// too long pauses are clamped to `MAX_RETRY_AFTER`.
+fn parse_retry_after(raw: &str) -> Option<Duration> {
+    let seconds = raw.trim().parse::<f64>().ok()?;
+    if !seconds.is_finite() || seconds < 0.0 {
+        return None;
+    }
+    Some(Duration::from_secs_f64(
+        seconds.min(MAX_RETRY_AFTER.as_secs_f64()),
+    ))
+}
+
+/// Error of the event sending
+#[derive(Debug)]
+pub enum SendError {
+    /// Sending failed after all retries, the event could be sent later
+    Failed(GelfError),
+    /// Sentry rejected the event, e.g. with 400 or 413, it must not be sent again
+    Rejected(GelfError),
+}
+
+impl Display for SendError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            SendError::Failed(e) => write!(f, "{}", e),
+            SendError::Rejected(e) => write!(f, "{}, event is rejected", e),
+        }
+    }
+}
+
+/// Sentry will not accept the event, when it is sent again, e.g. 400, 401, 403 or 413.
+/// 408 and 429 are temporary.
+fn is_rejected(status: StatusCode) -> bool {
+    status.is_client_error()
+        && status != StatusCode::REQUEST_TIMEOUT
+        && status != StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Transport of the single sentry project.
+/// Every project has own http client and own rate limits.
+#[derive(Clone)]
+pub struct SentryTransport {
+    dsn: Dsn,
+    label: String,
+    client: Client,
+    retry_policy: RetryPolicy,
+    rate_limits: Arc<Mutex<RateLimits>>,
+}
+
+impl SentryTransport {
+    pub fn new(dsn: Dsn, retry_policy: RetryPolicy) -> SentryTransport {
+        SentryTransport {
+            label: dsn.label(),
+            dsn,
+            client: Client::new(),
+            retry_policy,
+            rate_limits: Arc::new(Mutex::new(RateLimits::default())),
+        }
+    }
+
+    pub fn dsn(&self) -> &Dsn {
+        &self.dsn
+    }
+
+    /// Send prepared body to the sentry.
+    /// Waits while events are rate limited and retries failed requests with backoff.
+    pub async fn send(&self, endpoint: SentryEndpoint, body: Vec<u8>) -> Result<(), SendError> {
+        let result = self.send_with_retries(endpoint, body).await;
+        let counter: &IntCounterVec = match &result {
+            Ok(()) => {
+                health::sentry_accepted();
+                &SENTRY_EVENTS_SENT
+            }
+            Err(SendError::Failed(_)) => {
+                health::sentry_failed();
+                &SENTRY_EVENTS_FAILED
+            }
+            Err(SendError::Rejected(_)) => &SENTRY_EVENTS_FAILED,
+        };
+        counter.with_label_values(&[&self.label]).inc();
+        result
+    }
+
+    async fn send_with_retries(
+        &self,
+        endpoint: SentryEndpoint,
+        body: Vec<u8>,
+    ) -> Result<(), SendError> {
+        let url = self.dsn.api_url(endpoint.path());
+        let mut attempt = 0;
+
+        loop {
+            let limited_until = self
+                .rate_limits
+                .lock()
+                .unwrap()
+                .limited_until(EVENT_CATEGORY);
+            if let Some(until) = limited_until {
+                delay_until(TokioInstant::from_std(until)).await;
+            }
+
+            let started_at = Instant::now();
+            let response = self
+                .client
+                .post(url.as_str())
+                .header(X_SENTRY_AUTH, self.dsn.auth_header())
+                .header(CONTENT_TYPE, endpoint.content_type())
+                .body(body.clone())
+                .send()
+                .await;
+            SENTRY_REQUEST_DURATION
+                .with_label_values(&[&self.label])
+                .observe(started_at.elapsed().as_secs_f64());
+
+            let error = match response {
+                Ok(r) => {
+                    let status = r.status();
+                    self.rate_limits.lock().unwrap().update(status, r.headers());
+                    let text = r.text().await.unwrap_or_default();
+
+                    if status.is_success() {
+                        debug!("sentry response: {}", text);
+                        return Ok(());
+                    }
+                    if status == StatusCode::TOO_MANY_REQUESTS {
+                        SENTRY_EVENTS_RATE_LIMITED
+                            .with_label_values(&[&self.label])
+                            .inc();
+                        warn!("sentry rate limit is reached: {}", text);
+                        if attempt >= self.retry_policy.max_retries {
+                            return Err(SendError::Failed(GelfError::new(&format!(
+                                "sentry rate limit is reached: {}",
+                                text
+                            ))));
+                        }
+                        attempt += 1;
+                        continue;
+                    }
+                    let error = GelfError::new(&format!("sentry response {}: {}", status, text));
+                    if is_rejected(status) {
+                        return Err(SendError::Rejected(error));
+                    }
+                    if !status.is_server_error() && status != StatusCode::REQUEST_TIMEOUT {
+                        return Err(SendError::Failed(error));
+                    }
+                    error
+                }
+                Err(e) => GelfError::from_err("request sending for sentry error", e),
+            };
+
+            if attempt >= self.retry_policy.max_retries {
+                return Err(SendError::Failed(error));
+            }
+            warn!("{}, retrying", error);
+            delay_for(self.retry_policy.backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod limits {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        };
+
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_secs(1));
+        assert_eq!(policy.backoff(100), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_rejected() {
+        assert!(is_rejected(StatusCode::BAD_REQUEST));
+        assert!(is_rejected(StatusCode::UNAUTHORIZED));
+        assert!(is_rejected(StatusCode::PAYLOAD_TOO_LARGE));
+        assert!(!is_rejected(StatusCode::REQUEST_TIMEOUT));
+        assert!(!is_rejected(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_rejected(StatusCode::BAD_GATEWAY));
+    }
+
+    #[test]
+    fn test_sentry_rate_limits() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            X_SENTRY_RATE_LIMITS,
+            HeaderValue::from_static("60:error;transaction:key, 0.5:attachment:org"),
+        );
+
+        let mut limits = RateLimits::default();
+        limits.update(StatusCode::TOO_MANY_REQUESTS, &headers);
+
+        let until = limits.limited_until("error").unwrap();
+        assert!(until > Instant::now() + Duration::from_secs(59));
+        assert!(limits.limited_until("transaction").is_some());
+        assert!(limits.limited_until("attachment").is_some());
+        assert!(limits.limited_until("session").is_none());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(X_SENTRY_RATE_LIMITS, HeaderValue::from_static("120::org"));
+        limits.update(StatusCode::OK, &headers);
+        assert!(limits.limited_until("session").is_some());
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("30"));
+
+        let mut limits = RateLimits::default();
+        limits.update(StatusCode::OK, &headers);
+        assert!(limits.limited_until("error").is_none());
+
+        limits.update(StatusCode::TOO_MANY_REQUESTS, &headers);
+        let until = limits.limited_until("error").unwrap();
+        assert!(until > Instant::now() + Duration::from_secs(29));
+        assert!(limits.limited_until("transaction").is_some());
+    }
+
+    #[test]
+    fn test_invalid_retry_after() {
+        for raw in ["-1", "nan", "inf"] {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, HeaderValue::from_str(raw).unwrap());
+            let mut limits = RateLimits::default();
+            limits.update(StatusCode::TOO_MANY_REQUESTS, &headers);
+            let until = limits.limited_until("error").unwrap();
+            assert!(until <= Instant::now() + DEFAULT_RETRY_AFTER, "{}", raw);
+
+            let mut headers = HeaderMap::new();
+            let rate_limits = format!("{}:error, 10:transaction", raw);
+            headers.insert(
+                X_SENTRY_RATE_LIMITS,
+                HeaderValue::from_str(&rate_limits).unwrap(),
+            );
+            let mut limits = RateLimits::default();
+            limits.update(StatusCode::OK, &headers);
+            assert!(limits.limited_until("error").is_none(), "{}", raw);
+            assert!(limits.limited_until("transaction").is_some());
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert(X_SENTRY_RATE_LIMITS, HeaderValue::from_static("1e300::org"));
+        let mut limits = RateLimits::default();
+        limits.update(StatusCode::OK, &headers);
+        let until = limits.limited_until("error").unwrap();
+        assert!(until <= Instant::now() + MAX_RETRY_AFTER);
+
+        // 429, which limits other categories only, pauses the events too
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            X_SENTRY_RATE_LIMITS,
+            HeaderValue::from_static("60:transaction"),
+        );
+        let mut limits = RateLimits::default();
+        limits.update(StatusCode::TOO_MANY_REQUESTS, &headers);
+        assert!(limits.limited_until("error").is_some());
+    }
+}
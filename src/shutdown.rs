@@ -0,0 +1,153 @@
+use futures::future::{join_all, pending, select, Either, LocalBoxFuture};
+use futures::prelude::*;
+use log::{info, warn};
+use std::time::Duration;
+use tokio::sync::watch::{channel, Receiver, Sender};
+use tokio::time::timeout;
+
+/// Side of the graceful shutdown, which starts it
+pub struct ShutdownTrigger(Sender<bool>);
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        let _ = self.0.broadcast(true);
+    }
+}
+
+/// Signal of the graceful shutdown, acceptors stop taking new input when it is triggered
+#[derive(Clone)]
+pub struct Shutdown(Receiver<bool>);
+
+pub fn new_shutdown() -> (ShutdownTrigger, Shutdown) {
+    let (sender, receiver) = channel(false);
+    (ShutdownTrigger(sender), Shutdown(receiver))
+}
+
+impl Shutdown {
+    /// Resolves when the shutdown is triggered
+    pub async fn wait(mut self) {
+        while !*self.0.borrow() {
+            if self.0.recv().await.is_none() {
+                pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// Stream, which ends when the shutdown is triggered
+pub fn until_shutdown<S>(stream: S, shutdown: Shutdown) -> impl Stream<Item = S::Item>
+where
+    S: Stream,
+{
+    stream::unfold(
+        (Box::pin(stream), Box::pin(shutdown.wait())),
+        |(mut stream, shutdown)| async move {
+            match select(shutdown, stream.next()).await {
+                Either::Right((Some(item), shutdown)) => Some((item, (stream, shutdown))),
+                Either::Right((None, _)) | Either::Left(_) => None,
+            }
+        },
+    )
+}
+
+/// Run acceptors until SIGTERM or SIGINT, then stop them and flush the pipeline.
+/// Returns true, when everything is drained before the timeout.
+pub async fn run_until_signal<F, Fut>(
+    acceptors: Vec<LocalBoxFuture<'static, ()>>,
+    trigger: ShutdownTrigger,
+    drain_timeout: Duration,
+    flush: F,
+) -> bool
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let acceptors = match select(join_all(acceptors), Box::pin(wait_signal())).await {
+        Either::Left(_) => {
+            warn!("all acceptors are stopped, shutting down");
+            None
+        }
+        Either::Right((_, acceptors)) => {
+            info!(
+                "shutdown signal is received, draining in-flight events for {:?}",
+                drain_timeout
+            );
+            Some(acceptors)
+        }
+    };
+    trigger.trigger();
+
+    let drain = async move {
+        if let Some(acceptors) = acceptors {
+            acceptors.await;
+        }
+        flush().await
+    };
+    match timeout(drain_timeout, drain).await {
+        Ok(true) => {
+            info!("all events are drained");
+            true
+        }
+        Ok(false) => {
+            warn!("some events are not delivered");
+            false
+        }
+        Err(_) => {
+            warn!("drain timeout is reached, in-flight events are dropped");
+            false
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn wait_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen SIGTERM");
+    select(
+        Box::pin(terminate.recv()),
+        Box::pin(tokio::signal::ctrl_c()),
+    )
+    .await;
+}
+
+#[cfg(not(unix))]
+async fn wait_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod signal {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn test_until_shutdown() {
+        let (trigger, shutdown) = new_shutdown();
+        let mut items = Box::pin(until_shutdown(stream::iter(0..), shutdown.clone()));
+
+        assert_eq!(items.next().await, Some(0));
+        assert_eq!(items.next().await, Some(1));
+        trigger.trigger();
+        assert_eq!(items.next().await, None);
+        shutdown.wait().await;
+    }
+
+    #[actix_rt::test]
+    async fn test_drain() {
+        let (trigger, _) = new_shutdown();
+        let stopped = || -> Vec<LocalBoxFuture<'static, ()>> { vec![Box::pin(async {})] };
+
+        let drained = run_until_signal(stopped(), trigger, Duration::from_secs(1), || async {
+            true
+        })
+        .await;
+        assert!(drained);
+
+        let (trigger, _) = new_shutdown();
+        let drained = run_until_signal(stopped(), trigger, Duration::from_millis(10), || {
+            pending::<bool>()
+        })
+        .await;
+        assert!(!drained);
+    }
+}